@@ -0,0 +1,276 @@
+//! Per-chat automatic pruning: keeps a chat's stored chain size under an
+//! admin-chosen cap by progressively removing lower-count transitions,
+//! instead of requiring someone to prune manually.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::markov_chain::{Counter, TripletMarkovChain};
+use crate::theme::ThemeSettings;
+
+/// A chat's consent policy for learning from messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LearningPolicy {
+    /// Learn from everyone by default; a user must run `/optout` to be
+    /// excluded. This is the bot's original behavior.
+    #[default]
+    OptOut,
+    /// Learn from nobody until they explicitly run `/optin`.
+    OptIn,
+}
+
+/// Below this many transitions, a chain is left alone regardless of size, so
+/// auto-pruning can never wipe out a small chat's fledgling data.
+const MIN_TRANSITIONS_FLOOR: u64 = 50;
+
+/// Safety valve against a pathological chain that never shrinks below the
+/// cap no matter how high the threshold climbs.
+const MAX_PRUNE_PASSES: u32 = 1000;
+
+/// Per-chat settings controlling automatic pruning. A chat is opted in only
+/// when both fields are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatSettings {
+    /// The transition count threshold the first pruning pass starts at.
+    pub auto_prune_min_count: Option<Counter>,
+    /// The stored document size, in kilobytes, above which auto-pruning
+    /// kicks in.
+    pub auto_prune_max_document_kb: Option<u64>,
+    /// The chat's active "themed mode", if any, set via `/theme`.
+    pub theme: Option<ThemeSettings>,
+    /// This chat's consent policy for learning from messages.
+    #[serde(default)]
+    pub learning_policy: LearningPolicy,
+    /// Per-user consent overrides (set via `/optin`/`/optout`), keyed by the
+    /// user's Telegram ID as a string, matching the convention used for
+    /// per-user chain keys elsewhere. A missing entry falls back to
+    /// `learning_policy`'s default for that user.
+    #[serde(default)]
+    pub learning_consent: HashMap<String, bool>,
+    /// Whether the one-time explainer for switching to `opt_in` has already
+    /// been sent to this chat.
+    #[serde(default)]
+    pub learning_policy_explainer_sent: bool,
+    /// Per-chat overrides for whitelisted canned replies, keyed by
+    /// [`crate::templates::TemplateKey`]'s string form, set via
+    /// `/settemplate`. A missing entry falls back to the key's built-in
+    /// default text.
+    #[serde(default)]
+    pub reply_templates: HashMap<String, String>,
+    /// Whether this chat's command replies are rendered as JSON (see
+    /// `crate::presentation`) instead of prose by default, set via
+    /// `/jsonmode`. Can still be overridden per-request, e.g. `/msg json ...`.
+    #[serde(default)]
+    pub json_output: bool,
+    /// Nicknames for known users, set via `/alias add`, keyed by the alias's
+    /// lowercase form. Resolved to the user's ID at alias-creation time
+    /// rather than stored as a username, so the alias keeps working if the
+    /// aliased user later changes their Telegram username.
+    #[serde(default)]
+    pub aliases: HashMap<String, i64>,
+    /// Whether an alias should win over `/msg`'s literal-seed interpretation
+    /// even when the alias name also happens to be a word already in the
+    /// chat's chain, set via `/alias priority`. Off by default, since a
+    /// chat's existing vocabulary is the established behavior and shouldn't
+    /// quietly change meaning just because an alias with the same name gets
+    /// added later.
+    #[serde(default)]
+    pub alias_priority: bool,
+    /// Whether learning is currently paused in this chat, set via `/freeze`
+    /// and cleared via `/unfreeze`. `None` when never frozen or after an
+    /// explicit `/unfreeze`; a timed freeze is left in place until the next
+    /// message finds it expired (see [`Self::is_frozen`]), rather than
+    /// clearing itself proactively on a timer.
+    #[serde(default)]
+    pub frozen_until: Option<FreezeState>,
+    /// Whether `/summon` should skip mentioning a matching chat member, set
+    /// via `/summonmentions off`. `/summon` still generates a message either
+    /// way; this only controls whether it tries to ping someone.
+    #[serde(default)]
+    pub summon_mentions_disabled: bool,
+    /// Patterns whose matches are stripped out of a message before it's
+    /// learned, set via `/redact`. See [`crate::redaction`].
+    #[serde(default)]
+    pub redaction: crate::redaction::RedactionSettings,
+    /// Whether a user should be sent a one-time notice the first time a
+    /// message of theirs is learned in this chat, set via `/learnnotice`.
+    /// Off by default, so a busy chat doesn't get flooded with notices the
+    /// first time it's turned on. Meaningless (and never sent) in an
+    /// `opt_in` chat, since a user there has already explicitly consented
+    /// via `/optin` - see
+    /// [`crate::markov_telegram_bot::maybe_send_first_learn_notice`].
+    #[serde(default)]
+    pub learn_notice_enabled: bool,
+    /// Users who have already received the first-learn notice above, keyed
+    /// the same way as [`Self::learning_consent`], so the notice is a true
+    /// once-only event that survives a restart rather than an in-memory
+    /// guess.
+    #[serde(default)]
+    pub notified_users: HashSet<String>,
+}
+
+/// How long a chat's `/freeze` lasts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FreezeState {
+    /// Frozen until explicitly `/unfreeze`d.
+    Indefinite,
+    /// Frozen until this unix timestamp.
+    Until(i64),
+}
+
+impl ChatSettings {
+    /// Returns whether this chat has opted into automatic pruning.
+    pub fn auto_prune_enabled(&self) -> bool {
+        self.auto_prune_min_count.is_some() && self.auto_prune_max_document_kb.is_some()
+    }
+
+    /// Returns whether messages from `user_id` should be learned from, given
+    /// this chat's policy and any explicit per-user override.
+    pub fn is_learning_allowed(&self, user_id: i64) -> bool {
+        match self.learning_consent.get(&user_id.to_string()) {
+            Some(&consent) => consent,
+            None => self.learning_policy == LearningPolicy::OptOut,
+        }
+    }
+
+    /// Returns whether this chat's `/freeze` is currently in effect as of
+    /// `now`. A timed freeze that has expired reports `false` here, but is
+    /// left in [`Self::frozen_until`] for the caller to clear (see
+    /// [`crate::markov_telegram_bot::is_message_learning_allowed`]).
+    pub fn is_frozen(&self, now: i64) -> bool {
+        match self.frozen_until {
+            Some(FreezeState::Indefinite) => true,
+            Some(FreezeState::Until(expires_at)) => now < expires_at,
+            None => false,
+        }
+    }
+}
+
+/// The outcome of one auto-prune pass over a single chat's chain, for the
+/// audit log and admin notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    pub thresholds_applied: Vec<Counter>,
+    pub transitions_removed: usize,
+    pub final_size_kb: u64,
+}
+
+/// Estimates a chain's stored document size in kilobytes, from its JSON
+/// serialization (the same representation persisted to MongoDB, modulo BSON
+/// overhead).
+pub fn estimate_size_kb(chain: &TripletMarkovChain) -> u64 {
+    let bytes = serde_json::to_vec(chain).map(|encoded| encoded.len()).unwrap_or(0);
+    (bytes / 1024) as u64
+}
+
+/// Prunes `chain` in place, applying progressively higher count thresholds
+/// (starting at `starting_min_count`, i.e. count-1, then count-2, ...) until
+/// its estimated size is at or under `max_kb` or the floor is reached.
+/// Returns a report of what was done, or `None` if nothing was pruned.
+pub fn auto_prune(chain: &mut TripletMarkovChain, starting_min_count: Counter, max_kb: u64) -> Option<PruneReport> {
+    if chain.transition_count() <= MIN_TRANSITIONS_FLOOR || estimate_size_kb(chain) <= max_kb {
+        return None;
+    }
+
+    let mut thresholds_applied = Vec::new();
+    let mut transitions_removed = 0usize;
+
+    for threshold in starting_min_count.max(1)..starting_min_count.max(1) + MAX_PRUNE_PASSES {
+        if estimate_size_kb(chain) <= max_kb || chain.transition_count() <= MIN_TRANSITIONS_FLOOR {
+            break;
+        }
+
+        let removed = chain.prune_below(threshold);
+        if removed > 0 {
+            transitions_removed += removed;
+            thresholds_applied.push(threshold);
+        }
+    }
+
+    if transitions_removed == 0 {
+        return None;
+    }
+
+    Some(PruneReport {
+        thresholds_applied,
+        transitions_removed,
+        final_size_kb: estimate_size_kb(chain),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+
+    fn oversized_chain() -> TripletMarkovChain {
+        let mut builder = ChainBuilder::new();
+        for i in 0..500 {
+            builder = builder.msg(&format!("word{i} common transition"));
+        }
+        // Give "common transition" a high count so it survives pruning.
+        builder.weighted_msg("seed common transition", 500).build()
+    }
+
+    #[test]
+    fn auto_prune_shrinks_an_oversized_chain_under_the_cap() {
+        let mut chain = oversized_chain();
+        let before_size = estimate_size_kb(&chain);
+        let target_kb = before_size / 2;
+
+        let report = auto_prune(&mut chain, 1, target_kb).expect("expected pruning to occur");
+        assert!(report.transitions_removed > 0);
+        assert!(estimate_size_kb(&chain) <= target_kb || chain.transition_count() <= MIN_TRANSITIONS_FLOOR);
+    }
+
+    #[test]
+    fn auto_prune_leaves_a_chain_under_the_cap_untouched() {
+        let mut chain = oversized_chain();
+        let size = estimate_size_kb(&chain);
+
+        assert_eq!(auto_prune(&mut chain, 1, size + 100), None);
+    }
+
+    #[test]
+    fn auto_prune_never_shrinks_below_the_floor() {
+        let mut chain = TripletMarkovChain::new();
+        chain.add_message("only one small message");
+
+        assert_eq!(auto_prune(&mut chain, 1, 0), None);
+    }
+
+    #[test]
+    fn chat_settings_require_both_fields_to_opt_in() {
+        let mut settings = ChatSettings::default();
+        assert!(!settings.auto_prune_enabled());
+
+        settings.auto_prune_min_count = Some(1);
+        assert!(!settings.auto_prune_enabled());
+
+        settings.auto_prune_max_document_kb = Some(100);
+        assert!(settings.auto_prune_enabled());
+    }
+
+    #[test]
+    fn learning_allowed_under_opt_out_defaults_to_true_unless_overridden() {
+        let mut settings = ChatSettings::default();
+        assert!(settings.is_learning_allowed(1));
+
+        settings.learning_consent.insert("1".to_string(), false);
+        assert!(!settings.is_learning_allowed(1));
+        assert!(settings.is_learning_allowed(2));
+    }
+
+    #[test]
+    fn learning_allowed_under_opt_in_defaults_to_false_unless_overridden() {
+        let mut settings = ChatSettings { learning_policy: LearningPolicy::OptIn, ..Default::default() };
+        assert!(!settings.is_learning_allowed(1));
+
+        settings.learning_consent.insert("1".to_string(), true);
+        assert!(settings.is_learning_allowed(1));
+        assert!(!settings.is_learning_allowed(2));
+    }
+}
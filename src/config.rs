@@ -0,0 +1,85 @@
+//! Configuration loaded from the process environment.
+
+use std::env;
+
+/// Runtime configuration for the bot, loaded once at startup.
+pub struct Config {
+    /// Bot tokens to run, one dispatcher/polling loop per token, all sharing
+    /// this process's storage, caches, scheduler, and health state - see
+    /// `main.rs`'s multi-bot startup. Always has at least one entry, from the
+    /// required `TELOXIDE_TOKEN`; further entries come from the
+    /// comma-separated `TELOXIDE_EXTRA_TOKENS`, for an operator running (say)
+    /// one bot per language community against a single database.
+    pub bot_tokens: Vec<String>,
+    pub mongo_uri: String,
+    pub mongo_db_name: String,
+    /// Directory to append nightly per-chat statistics CSVs to. Disabled if
+    /// unset.
+    pub stats_export_dir: Option<String>,
+    /// Telegram user ID allowed to run the owner-only `/perf` command.
+    /// Disabled (i.e. `/perf` refuses everyone) if unset.
+    pub owner_user_id: Option<i64>,
+    /// Long-poll timeout for `getUpdates`, in seconds. Telegram holds the
+    /// connection open up to this long waiting for a new update before
+    /// responding empty, trading a little latency for far fewer wasted round
+    /// trips than a short (or absent) timeout would busy-poll with.
+    pub poll_timeout_secs: u64,
+    /// Maximum number of updates fetched per `getUpdates` call. Telegram
+    /// accepts 1-100; this isn't validated here, since [`Polling::builder`]'s
+    /// own `limit` setter already panics on an out-of-range value.
+    ///
+    /// [`Polling::builder`]: teloxide::update_listeners::Polling::builder
+    pub poll_limit: u8,
+    /// Whether to request inline query updates. See [`crate::polling`].
+    pub enable_inline_queries: bool,
+    /// Whether to request callback query updates. See [`crate::polling`].
+    pub enable_callback_queries: bool,
+    /// Whether to request the bot's own chat-membership-change updates. See
+    /// [`crate::polling`].
+    pub enable_chat_membership_updates: bool,
+    /// Whether to request `channel_post` and `edited_channel_post` updates,
+    /// so the bot can learn from and serve `/msg` in channels it's an admin
+    /// of. See [`crate::polling`].
+    pub enable_channel_posts: bool,
+}
+
+impl Config {
+    /// Reads configuration from environment variables, panicking with a
+    /// descriptive message if a required variable is missing.
+    pub fn from_env() -> Self {
+        Self {
+            bot_tokens: bot_tokens_from_env(),
+            mongo_uri: require_env("MONGO_URI"),
+            mongo_db_name: env::var("MONGO_DB_NAME").unwrap_or_else(|_| "markov".to_string()),
+            stats_export_dir: env::var("STATS_EXPORT_DIR").ok(),
+            owner_user_id: env::var("BOT_OWNER_USER_ID").ok().and_then(|value| value.parse().ok()),
+            poll_timeout_secs: env_or("POLL_TIMEOUT_SECS", 50),
+            poll_limit: env_or("POLL_LIMIT", 100),
+            enable_inline_queries: env_or("ENABLE_INLINE_QUERIES", true),
+            enable_callback_queries: env_or("ENABLE_CALLBACK_QUERIES", true),
+            enable_chat_membership_updates: env_or("ENABLE_CHAT_MEMBERSHIP_UPDATES", true),
+            enable_channel_posts: env_or("ENABLE_CHANNEL_POSTS", true),
+        }
+    }
+}
+
+/// Builds [`Config::bot_tokens`]: the required `TELOXIDE_TOKEN`, followed by
+/// any further tokens in the comma-separated `TELOXIDE_EXTRA_TOKENS`, in
+/// order, blank entries dropped.
+fn bot_tokens_from_env() -> Vec<String> {
+    let mut tokens = vec![require_env("TELOXIDE_TOKEN")];
+    if let Ok(extra) = env::var("TELOXIDE_EXTRA_TOKENS") {
+        tokens.extend(extra.split(',').map(str::trim).filter(|token| !token.is_empty()).map(str::to_string));
+    }
+    tokens
+}
+
+fn require_env(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| panic!("missing required environment variable: {name}"))
+}
+
+/// Reads and parses an optional environment variable, falling back to
+/// `default` if it's unset or fails to parse.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
@@ -0,0 +1,163 @@
+//! A bounded, in-memory holding queue for learn operations that failed even
+//! at the journal layer (see
+//! [`crate::markov_telegram_bot::learn_with_journal`]), meaning storage
+//! itself was unreachable rather than just interrupted mid-write. The learn
+//! journal already covers a crash or a single failed write; this queue
+//! covers the case the journal can't reach: a sustained outage where the
+//! journal write itself fails, leaving nowhere durable to record the pending
+//! learn until storage comes back. Held in memory only - a restart during an
+//! outage still loses whatever's queued, which is an accepted gap given the
+//! added complexity of yet another durable store on top of the journal.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::markov_telegram_bot::{learn_into, Storage};
+
+/// How many failed learns are held before the oldest is evicted, to bound
+/// memory during a long outage.
+const MAX_QUEUE_DEPTH: usize = 1_000;
+
+/// How long a failed learn is kept for retry before being dropped as too
+/// stale to matter.
+const MAX_ENTRY_AGE_SECS: i64 = 24 * 60 * 60;
+
+/// One learn operation that failed even at the journal layer, held for retry
+/// once storage is reachable again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedLearn {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub text: String,
+    pub failed_at: i64,
+}
+
+/// A bounded FIFO of [`FailedLearn`] entries, evicting oldest-first past
+/// [`MAX_QUEUE_DEPTH`].
+#[derive(Default)]
+pub struct DeadLetterQueue {
+    entries: Mutex<VecDeque<FailedLearn>>,
+}
+
+impl DeadLetterQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `entry`, evicting and logging the oldest entry if this pushes
+    /// the queue past [`MAX_QUEUE_DEPTH`].
+    pub fn push(&self, entry: FailedLearn) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(entry);
+        if entries.len() > MAX_QUEUE_DEPTH {
+            if let Some(evicted) = entries.pop_front() {
+                log::error!(
+                    "dead-letter queue full, dropping queued learn for chat {} user {}",
+                    evicted.chat_id, evicted.user_id,
+                );
+            }
+        }
+    }
+
+    /// Drops entries older than [`MAX_ENTRY_AGE_SECS`] as of `now`, logging
+    /// each one dropped.
+    pub fn drain_expired(&self, now: i64) {
+        self.entries.lock().unwrap().retain(|entry| {
+            let age = now - entry.failed_at;
+            let expired = age > MAX_ENTRY_AGE_SECS;
+            if expired {
+                log::error!(
+                    "dropping dead-lettered learn for chat {} user {} after {age}s unretried",
+                    entry.chat_id, entry.user_id,
+                );
+            }
+            !expired
+        });
+    }
+
+    /// The number of entries currently queued, reported as a cache size by
+    /// `/status` (see [`crate::health`]).
+    pub fn depth(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Attempts to re-learn every currently queued entry directly via
+    /// [`learn_into`], rather than
+    /// [`crate::markov_telegram_bot::learn_with_journal`], since a
+    /// dead-lettered entry has no message ID to key a journal entry on,
+    /// having already been queued outside that mechanism. Removes each entry
+    /// that succeeds, leaving the rest queued for the next attempt. Returns
+    /// the number successfully retried.
+    pub async fn retry_all(&self, storage: &dyn Storage) -> usize {
+        let entries: Vec<FailedLearn> = self.entries.lock().unwrap().drain(..).collect();
+
+        let mut retried = 0;
+        for entry in entries {
+            match learn_into(storage, entry.chat_id, None, entry.user_id, &entry.text).await {
+                Ok(()) => retried += 1,
+                Err(err) => {
+                    log::error!(
+                        "dead-letter retry failed for chat {} user {}: {err}",
+                        entry.chat_id, entry.user_id,
+                    );
+                    self.entries.lock().unwrap().push_back(entry);
+                }
+            }
+        }
+        retried
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markov_telegram_bot::InMemoryStorage;
+
+    fn sample(chat_id: i64, failed_at: i64) -> FailedLearn {
+        FailedLearn { chat_id, user_id: 1, text: "hello world".to_string(), failed_at }
+    }
+
+    #[test]
+    fn push_evicts_the_oldest_entry_once_past_max_depth() {
+        let queue = DeadLetterQueue::new();
+        for i in 0..MAX_QUEUE_DEPTH {
+            queue.push(sample(i as i64, 0));
+        }
+        assert_eq!(queue.depth(), MAX_QUEUE_DEPTH);
+
+        queue.push(sample(MAX_QUEUE_DEPTH as i64, 0));
+        assert_eq!(queue.depth(), MAX_QUEUE_DEPTH);
+    }
+
+    #[test]
+    fn drain_expired_drops_only_entries_past_the_max_age() {
+        let queue = DeadLetterQueue::new();
+        queue.push(sample(1, 0));
+        queue.push(sample(2, 100));
+
+        queue.drain_expired(MAX_ENTRY_AGE_SECS + 50);
+        assert_eq!(queue.depth(), 1);
+
+        queue.drain_expired(MAX_ENTRY_AGE_SECS + 150);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn retry_all_clears_entries_once_storage_recovers() {
+        let storage = InMemoryStorage::new();
+        let queue = DeadLetterQueue::new();
+        queue.push(sample(1, 0));
+        queue.push(sample(2, 0));
+
+        storage.set_down(true);
+        assert_eq!(queue.retry_all(&storage).await, 0);
+        assert_eq!(queue.depth(), 2);
+
+        storage.set_down(false);
+        assert_eq!(queue.retry_all(&storage).await, 2);
+        assert_eq!(queue.depth(), 0);
+
+        let chat_data = storage.read_chat_data(1).await.unwrap().unwrap();
+        assert!(chat_data.data.contains_key("1"));
+    }
+}
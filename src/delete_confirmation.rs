@@ -0,0 +1,133 @@
+//! Tracks pending `/deletemy` confirmations, per (chat, user), so a second
+//! `/deletemy` run before the first is confirmed cleanly supersedes it
+//! instead of leaving two outstanding prompts a user could land on
+//! confusingly (a reply, or here a button press, against the older one
+//! previously did nothing with no explanation).
+//!
+//! Like [`crate::quarantine::QuarantineBuffer`], a stale button press is
+//! handled by answering the callback query rather than editing the earlier
+//! message - this bot doesn't otherwise edit messages after sending them
+//! (see `crate::markov_telegram_bot::handle_delete_confirmation_callback`),
+//! so introducing that just for this one flow would be new surface area for
+//! a problem the callback-query answer already solves.
+//!
+//! Like the bot's other in-process trackers, this state lives only for the
+//! process lifetime; losing it on restart just drops any outstanding
+//! confirmation, which is harmless since nothing has been deleted yet.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// One outstanding `/deletemy` confirmation, identified by the ID of the
+/// prompt message its buttons are attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pending {
+    prompt_message_id: i64,
+}
+
+/// The outcome of resolving a button press or `/cancel` against the tracked
+/// confirmation for a (chat, user).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptLookup {
+    /// The press matched the current outstanding prompt; the caller should
+    /// act on it (confirm or cancel). The entry has already been cleared.
+    Current,
+    /// A confirmation is pending, but for a different, since-superseded
+    /// prompt message - most likely a stale button on an older `/deletemy`.
+    Superseded,
+    /// No confirmation is currently pending for this (chat, user).
+    NoneOutstanding,
+}
+
+/// Tracks at most one outstanding `/deletemy` confirmation per (chat, user).
+#[derive(Default)]
+pub struct DeleteConfirmations {
+    pending: Mutex<HashMap<(i64, i64), Pending>>,
+}
+
+impl DeleteConfirmations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a freshly sent confirmation prompt for (chat_id, user_id),
+    /// silently superseding any prior one - a later press against that
+    /// prior prompt will resolve as [`PromptLookup::Superseded`].
+    pub fn start(&self, chat_id: i64, user_id: i64, prompt_message_id: i64) {
+        self.pending.lock().unwrap().insert((chat_id, user_id), Pending { prompt_message_id });
+    }
+
+    /// Resolves a button press for (chat_id, user_id) that came from
+    /// `message_id`. Clears the entry when it matches the current prompt,
+    /// since either a confirm or a cancel ends the flow; leaves a
+    /// superseded entry in place so the actual live prompt still resolves
+    /// normally afterward.
+    pub fn resolve(&self, chat_id: i64, user_id: i64, message_id: i64) -> PromptLookup {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get(&(chat_id, user_id)) {
+            Some(current) if current.prompt_message_id == message_id => {
+                pending.remove(&(chat_id, user_id));
+                PromptLookup::Current
+            }
+            Some(_) => PromptLookup::Superseded,
+            None => PromptLookup::NoneOutstanding,
+        }
+    }
+
+    /// Clears any pending confirmation for (chat_id, user_id), for `/cancel`
+    /// (which has no specific button press to match against). Returns
+    /// whether one was actually outstanding.
+    pub fn cancel(&self, chat_id: i64, user_id: i64) -> bool {
+        self.pending.lock().unwrap().remove(&(chat_id, user_id)).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolving_the_current_prompt_confirms_and_clears_it() {
+        let confirmations = DeleteConfirmations::new();
+        confirmations.start(1, 42, 100);
+
+        assert_eq!(confirmations.resolve(1, 42, 100), PromptLookup::Current);
+        assert_eq!(confirmations.resolve(1, 42, 100), PromptLookup::NoneOutstanding);
+    }
+
+    #[test]
+    fn starting_a_new_prompt_supersedes_the_old_one() {
+        let confirmations = DeleteConfirmations::new();
+        confirmations.start(1, 42, 100);
+        confirmations.start(1, 42, 200);
+
+        assert_eq!(confirmations.resolve(1, 42, 100), PromptLookup::Superseded);
+        assert_eq!(confirmations.resolve(1, 42, 200), PromptLookup::Current);
+    }
+
+    #[test]
+    fn resolve_with_nothing_pending_reports_none_outstanding() {
+        let confirmations = DeleteConfirmations::new();
+        assert_eq!(confirmations.resolve(1, 42, 100), PromptLookup::NoneOutstanding);
+    }
+
+    #[test]
+    fn confirmations_are_isolated_per_chat_and_user() {
+        let confirmations = DeleteConfirmations::new();
+        confirmations.start(1, 42, 100);
+
+        assert_eq!(confirmations.resolve(2, 42, 100), PromptLookup::NoneOutstanding);
+        assert_eq!(confirmations.resolve(1, 7, 100), PromptLookup::NoneOutstanding);
+        assert_eq!(confirmations.resolve(1, 42, 100), PromptLookup::Current);
+    }
+
+    #[test]
+    fn cancel_clears_a_pending_confirmation_and_reports_whether_one_existed() {
+        let confirmations = DeleteConfirmations::new();
+        assert!(!confirmations.cancel(1, 42));
+
+        confirmations.start(1, 42, 100);
+        assert!(confirmations.cancel(1, 42));
+        assert_eq!(confirmations.resolve(1, 42, 100), PromptLookup::NoneOutstanding);
+    }
+}
@@ -0,0 +1,200 @@
+//! A `gc-users` CLI subcommand: deletes `user_infos` rows (see
+//! [`crate::markov_telegram_bot::UserInfo`]) whose user_id has no
+//! corresponding chain in their chat's [`crate::markov_telegram_bot::ChatData::data`],
+//! e.g. because `/deleteme` or an auto-prune already removed it, and whose
+//! `last_seen` predates a configurable cutoff. Left unchecked, this
+//! collection grows forever, since a user's info row otherwise long outlives
+//! their chain data.
+
+use crate::config::Config;
+use crate::markov_telegram_bot::{ChainLookup, MongoStorage, Storage};
+
+/// What one GC pass did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcSummary {
+    pub rows_scanned: usize,
+    pub deleted: Vec<(i64, i64)>,
+}
+
+/// Deletes every `user_infos` row whose `(chat_id, user_id)` has no
+/// corresponding chain and whose `last_seen` is at least `max_age_secs` old
+/// as of `now_unix`.
+///
+/// Re-checks each candidate's `last_seen` via a fresh
+/// [`Storage::get_user_info_by_id`] lookup immediately before deleting it, so
+/// a user who speaks again mid-pass, refreshing `last_seen` and re-creating
+/// their chain, isn't deleted out from under themselves by a decision made
+/// against the stale snapshot [`Storage::list_all_user_infos`] returned at
+/// the start of the sweep.
+pub async fn run_gc(storage: &dyn Storage, max_age_secs: i64, now_unix: i64) -> GcSummary {
+    let mut summary = GcSummary::default();
+
+    let all_infos = match storage.list_all_user_infos().await {
+        Ok(infos) => infos,
+        Err(err) => {
+            log::error!("gc-users: failed to list user infos: {err}");
+            return summary;
+        }
+    };
+    summary.rows_scanned = all_infos.len();
+
+    for info in all_infos {
+        let has_chain = match storage.read_user_chain(info.chat_id, &info.user_id.to_string()).await {
+            Ok(ChainLookup::Found(_)) => true,
+            Ok(ChainLookup::ChatAbsent | ChainLookup::KeyAbsent) => false,
+            Err(err) => {
+                log::error!("gc-users: failed to check chain for user {} in chat {}: {err}", info.user_id, info.chat_id);
+                continue;
+            }
+        };
+        if has_chain || now_unix - info.last_seen < max_age_secs {
+            continue;
+        }
+
+        match storage.get_user_info_by_id(info.chat_id, info.user_id).await {
+            Ok(Some(fresh)) if now_unix - fresh.last_seen >= max_age_secs => {}
+            Ok(_) => continue,
+            Err(err) => {
+                log::error!("gc-users: failed to re-check user {} in chat {}: {err}", info.user_id, info.chat_id);
+                continue;
+            }
+        }
+
+        if let Err(err) = storage.delete_user_info(info.chat_id, info.user_id).await {
+            log::error!("gc-users: failed to delete user {} in chat {}: {err}", info.user_id, info.chat_id);
+            continue;
+        }
+        summary.deleted.push((info.chat_id, info.user_id));
+    }
+
+    summary
+}
+
+/// The `--max-age` flag, e.g. `--max-age 30d` (parsed with
+/// [`crate::theme::parse_duration_secs`]). Falls back to
+/// [`DEFAULT_MAX_AGE_SECS`] if absent or unparsable.
+const MAX_AGE_FLAG: &str = "--max-age";
+
+/// How stale a candidate's `last_seen` must be before `gc-users` deletes it,
+/// when `--max-age` isn't given. Also the age used by
+/// [`crate::main`]'s periodic GC schedule.
+pub(crate) const DEFAULT_MAX_AGE_SECS: i64 = 90 * 24 * 60 * 60;
+
+fn parse_max_age_flag(args: &[String]) -> i64 {
+    args.iter()
+        .position(|arg| arg == MAX_AGE_FLAG)
+        .and_then(|index| args.get(index + 1))
+        .and_then(|value| crate::theme::parse_duration_secs(value))
+        .unwrap_or(DEFAULT_MAX_AGE_SECS)
+}
+
+/// Runs the `gc-users` subcommand against the MongoDB configured via
+/// [`Config::from_env`]. Exits the process with a nonzero code on a
+/// connection failure, so it can be wired into a deploy step.
+pub async fn run_gc_users_cli(args: &[String]) {
+    let max_age_secs = parse_max_age_flag(args);
+    let config = Config::from_env();
+    let storage = match MongoStorage::connect(&config.mongo_uri, &config.mongo_db_name).await {
+        Ok(storage) => storage,
+        Err(err) => {
+            eprintln!("gc-users: failed to connect to MongoDB: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    let summary = run_gc(&storage, max_age_secs, chrono::Utc::now().timestamp()).await;
+    println!(
+        "scanned {} row(s), deleted {}: {:?}",
+        summary.rows_scanned,
+        summary.deleted.len(),
+        summary.deleted
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markov_chain::TripletMarkovChain;
+    use crate::markov_telegram_bot::{ChatData, InMemoryStorage, UserInfo};
+
+    const MAX_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+
+    #[tokio::test]
+    async fn a_stale_user_with_no_chain_is_deleted() {
+        let storage = InMemoryStorage::new();
+        storage
+            .put_user_info(&UserInfo { chat_id: 1, user_id: 42, username: Some("dave".to_string()), first_name: "Dave".to_string(), last_seen: 0 })
+            .await
+            .unwrap();
+
+        let summary = run_gc(&storage, MAX_AGE_SECS, MAX_AGE_SECS + 1).await;
+        assert_eq!(summary.rows_scanned, 1);
+        assert_eq!(summary.deleted, vec![(1, 42)]);
+        assert!(storage.get_user_info_by_id(1, 42).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_user_with_an_existing_chain_is_kept_regardless_of_age() {
+        let storage = InMemoryStorage::new();
+        storage
+            .put_user_info(&UserInfo { chat_id: 1, user_id: 42, username: Some("dave".to_string()), first_name: "Dave".to_string(), last_seen: 0 })
+            .await
+            .unwrap();
+        let mut chain = TripletMarkovChain::new();
+        chain.add_message("hello world");
+        storage.write_chat_data(1, &ChatData { data: [("42".to_string(), chain)].into(), ..Default::default() }).await.unwrap();
+
+        let summary = run_gc(&storage, MAX_AGE_SECS, MAX_AGE_SECS + 1).await;
+        assert_eq!(summary.deleted, Vec::new());
+        assert!(storage.get_user_info_by_id(1, 42).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn a_user_with_no_chain_but_a_recent_last_seen_is_kept() {
+        let storage = InMemoryStorage::new();
+        storage
+            .put_user_info(&UserInfo { chat_id: 1, user_id: 42, username: Some("dave".to_string()), first_name: "Dave".to_string(), last_seen: 100 })
+            .await
+            .unwrap();
+
+        let summary = run_gc(&storage, MAX_AGE_SECS, 100 + MAX_AGE_SECS - 1).await;
+        assert_eq!(summary.deleted, Vec::new());
+        assert!(storage.get_user_info_by_id(1, 42).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn a_candidate_whose_last_seen_was_refreshed_after_the_snapshot_survives_the_race_recheck() {
+        let storage = InMemoryStorage::new();
+        storage
+            .put_user_info(&UserInfo { chat_id: 1, user_id: 42, username: Some("dave".to_string()), first_name: "Dave".to_string(), last_seen: 0 })
+            .await
+            .unwrap();
+
+        // Simulate the user speaking again after `list_all_user_infos` took
+        // its snapshot but before the delete: refresh `last_seen` right
+        // before running the pass, standing in for the write that would
+        // otherwise race with it.
+        storage
+            .put_user_info(&UserInfo { chat_id: 1, user_id: 42, username: Some("dave".to_string()), first_name: "Dave".to_string(), last_seen: MAX_AGE_SECS })
+            .await
+            .unwrap();
+
+        let summary = run_gc(&storage, MAX_AGE_SECS, MAX_AGE_SECS + 1).await;
+        assert_eq!(summary.deleted, Vec::new());
+        assert!(storage.get_user_info_by_id(1, 42).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn an_empty_storage_scans_nothing() {
+        let storage = InMemoryStorage::new();
+        let summary = run_gc(&storage, MAX_AGE_SECS, 0).await;
+        assert_eq!(summary, GcSummary::default());
+    }
+
+    #[test]
+    fn parse_max_age_flag_reads_a_duration_or_falls_back_to_the_default() {
+        assert_eq!(parse_max_age_flag(&["--max-age".to_string(), "7d".to_string()]), 7 * 86_400);
+        assert_eq!(parse_max_age_flag(&[]), DEFAULT_MAX_AGE_SECS);
+        assert_eq!(parse_max_age_flag(&["--max-age".to_string(), "nonsense".to_string()]), DEFAULT_MAX_AGE_SECS);
+    }
+}
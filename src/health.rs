@@ -0,0 +1,231 @@
+//! Cheap, always-on runtime health tracking, surfaced to chat admins and the
+//! bot owner via `/status`: uptime, the last time an update was successfully
+//! routed to a handler, the last storage error (if any), a handful of
+//! in-process cache sizes, and how deep the learn journal's write buffer
+//! currently is. Every field is behind an atomic or a small [`Mutex`], since
+//! this is touched on the hot path of every processed update.
+//!
+//! There's no hook in teloxide's dispatcher for "a `get_updates` poll
+//! completed but returned nothing", so [`HealthState::record_poll`] is
+//! called instead from [`crate::markov_telegram_bot::handler`]'s top-level
+//! `inspect_async`, which runs for every update that *is* delivered. An idle
+//! chat with no traffic will look identical to a stalled poller here; that's
+//! an accepted gap rather than something worth adding fake keep-alive
+//! traffic to paper over.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// How stale [`HealthState::last_poll_at`] can get before [`HealthState::report`]
+/// calls the bot "degraded" instead of healthy.
+const STALE_POLL_THRESHOLD_SECS: i64 = 120;
+
+/// How many characters of a storage error [`HealthState::record_storage_error`]
+/// keeps, so one long error message can't dominate `/status`'s output.
+const MAX_STORED_ERROR_LEN: usize = 200;
+
+/// Tracks the bot's own runtime health. Cloned behind an `Arc` and shared
+/// between the dispatcher, the storage layer's callers, and the maintenance
+/// schedules in `main`.
+pub struct HealthState {
+    started_at_unix: i64,
+    last_poll_at_unix: AtomicI64,
+    last_storage_error: Mutex<Option<String>>,
+    cache_sizes: Mutex<HashMap<&'static str, usize>>,
+    pending_write_buffer_depth: AtomicUsize,
+    /// How many messages have been skipped by the ingestion pipeline since
+    /// startup, by reason (e.g. `"frozen"`, `"consent"` - see
+    /// [`crate::markov_telegram_bot::LearningVerdict::skip_reason`]).
+    skip_counts: Mutex<HashMap<&'static str, u64>>,
+    /// How many bot tokens this process is running (see `main.rs`'s
+    /// multi-bot startup). `0` until [`Self::set_bot_count`] is called;
+    /// reported by [`Self::report`] so `/status` reflects, at a glance,
+    /// whether it's aggregating across more than one bot. `1` for the common
+    /// single-bot deployment.
+    bot_count: AtomicUsize,
+}
+
+impl HealthState {
+    pub fn new(now: i64) -> Self {
+        Self {
+            started_at_unix: now,
+            last_poll_at_unix: AtomicI64::new(0),
+            last_storage_error: Mutex::new(None),
+            cache_sizes: Mutex::new(HashMap::new()),
+            pending_write_buffer_depth: AtomicUsize::new(0),
+            skip_counts: Mutex::new(HashMap::new()),
+            bot_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records how many bot tokens this process is running, once at startup.
+    pub fn set_bot_count(&self, count: usize) {
+        self.bot_count.store(count, Ordering::Relaxed);
+    }
+
+    /// Records that an update was successfully routed to a handler at `now`.
+    pub fn record_poll(&self, now: i64) {
+        self.last_poll_at_unix.store(now, Ordering::Relaxed);
+    }
+
+    /// Records `err` as the most recent storage error, truncated to
+    /// [`MAX_STORED_ERROR_LEN`] characters.
+    pub fn record_storage_error(&self, err: impl std::fmt::Display) {
+        let mut message = err.to_string();
+        if message.len() > MAX_STORED_ERROR_LEN {
+            message.truncate(MAX_STORED_ERROR_LEN);
+            message.push('\u{2026}');
+        }
+        *self.last_storage_error.lock().unwrap() = Some(message);
+    }
+
+    /// Records the current size of a named in-process cache, e.g. the sent
+    /// message tracker's recent-echo buffer.
+    pub fn set_cache_size(&self, name: &'static str, size: usize) {
+        self.cache_sizes.lock().unwrap().insert(name, size);
+    }
+
+    /// Records how many entries are currently sitting in the learn journal's
+    /// write buffer, awaiting a successful write or recovery.
+    pub fn set_pending_write_buffer_depth(&self, depth: usize) {
+        self.pending_write_buffer_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Records one more message skipped by the ingestion pipeline for
+    /// `reason`, since startup.
+    pub fn record_skip(&self, reason: &'static str) {
+        *self.skip_counts.lock().unwrap().entry(reason).or_insert(0) += 1;
+    }
+
+    /// Formats a compact `/status` report as of `now`. Calls the bot
+    /// "degraded" when the last poll is missing or older than
+    /// [`STALE_POLL_THRESHOLD_SECS`].
+    pub fn report(&self, now: i64) -> String {
+        let uptime_secs = (now - self.started_at_unix).max(0);
+
+        let last_poll_unix = self.last_poll_at_unix.load(Ordering::Relaxed);
+        let (healthy, last_poll_desc) = match last_poll_unix {
+            0 => (false, "never".to_string()),
+            ts => (now - ts <= STALE_POLL_THRESHOLD_SECS, format!("{}s ago", (now - ts).max(0))),
+        };
+
+        let last_error = self.last_storage_error.lock().unwrap().clone().unwrap_or_else(|| "none".to_string());
+
+        let mut cache_sizes: Vec<(&str, usize)> = self.cache_sizes.lock().unwrap().iter().map(|(&k, &v)| (k, v)).collect();
+        cache_sizes.sort_unstable_by_key(|(name, _)| *name);
+        let cache_report = if cache_sizes.is_empty() {
+            "none".to_string()
+        } else {
+            cache_sizes.into_iter().map(|(name, size)| format!("{name}={size}")).collect::<Vec<_>>().join(", ")
+        };
+
+        let pending = self.pending_write_buffer_depth.load(Ordering::Relaxed);
+
+        let mut skip_counts: Vec<(&str, u64)> = self.skip_counts.lock().unwrap().iter().map(|(&k, &v)| (k, v)).collect();
+        skip_counts.sort_unstable_by_key(|(reason, _)| *reason);
+        let skip_report = if skip_counts.is_empty() {
+            "none".to_string()
+        } else {
+            skip_counts.into_iter().map(|(reason, count)| format!("{reason}={count}")).collect::<Vec<_>>().join(", ")
+        };
+
+        let bot_count = self.bot_count.load(Ordering::Relaxed);
+
+        format!(
+            "Status: {}\nBots: {bot_count}\nUptime: {uptime_secs}s\nLast poll: {last_poll_desc}\nLast storage error: {last_error}\nCache sizes: {cache_report}\nPending write-buffer depth: {pending}\nSkip counts: {skip_report}",
+            if healthy { "healthy" } else { "degraded" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_is_healthy_with_a_recent_poll_and_no_errors() {
+        let health = HealthState::new(1_000);
+        health.record_poll(1_050);
+
+        let report = health.report(1_060);
+        assert!(report.contains("Status: healthy"));
+        assert!(report.contains("Uptime: 60s"));
+        assert!(report.contains("Last poll: 10s ago"));
+        assert!(report.contains("Last storage error: none"));
+        assert!(report.contains("Cache sizes: none"));
+        assert!(report.contains("Pending write-buffer depth: 0"));
+    }
+
+    #[test]
+    fn report_is_degraded_when_the_last_poll_is_stale() {
+        let health = HealthState::new(1_000);
+        health.record_poll(1_000);
+
+        let report = health.report(1_000 + STALE_POLL_THRESHOLD_SECS + 1);
+        assert!(report.contains("degraded"));
+    }
+
+    #[test]
+    fn report_is_degraded_when_no_poll_has_ever_happened() {
+        let health = HealthState::new(1_000);
+        assert!(health.report(1_100).contains("degraded"));
+        assert!(health.report(1_100).contains("Last poll: never"));
+    }
+
+    #[test]
+    fn report_includes_the_latest_storage_error_truncated() {
+        let health = HealthState::new(0);
+        health.record_storage_error("a".repeat(MAX_STORED_ERROR_LEN + 50));
+
+        let report = health.report(0);
+        let error_line = report.lines().find(|line| line.starts_with("Last storage error:")).unwrap();
+        assert_eq!(error_line.len(), "Last storage error: ".len() + MAX_STORED_ERROR_LEN + '\u{2026}'.len_utf8());
+    }
+
+    #[test]
+    fn report_lists_cache_sizes_sorted_by_name() {
+        let health = HealthState::new(0);
+        health.set_cache_size("sent_message_tracker", 12);
+        health.set_cache_size("aliases", 3);
+
+        let report = health.report(0);
+        assert!(report.contains("Cache sizes: aliases=3, sent_message_tracker=12"));
+    }
+
+    #[test]
+    fn report_includes_the_pending_write_buffer_depth() {
+        let health = HealthState::new(0);
+        health.set_pending_write_buffer_depth(4);
+        assert!(health.report(0).contains("Pending write-buffer depth: 4"));
+    }
+
+    #[test]
+    fn report_lists_skip_counts_sorted_by_reason() {
+        let health = HealthState::new(0);
+        health.record_skip("frozen");
+        health.record_skip("frozen");
+        health.record_skip("consent");
+
+        assert!(health.report(0).contains("Skip counts: consent=1, frozen=2"));
+    }
+
+    #[test]
+    fn report_says_none_when_nothing_has_been_skipped() {
+        let health = HealthState::new(0);
+        assert!(health.report(0).contains("Skip counts: none"));
+    }
+
+    #[test]
+    fn report_defaults_to_zero_bots_until_set() {
+        let health = HealthState::new(0);
+        assert!(health.report(0).contains("Bots: 0"));
+    }
+
+    #[test]
+    fn report_includes_the_configured_bot_count() {
+        let health = HealthState::new(0);
+        health.set_bot_count(3);
+        assert!(health.report(0).contains("Bots: 3"));
+    }
+}
@@ -0,0 +1,87 @@
+//! A humanization delay for auto-generated, unprompted replies: how long a
+//! reply should be held back (with a typing indicator shown in the
+//! meantime) so it doesn't land in a fast-moving chat instantly and read as
+//! obviously bot-generated.
+//!
+//! Not wired into any send path yet. This bot has no unprompted,
+//! auto-generated reply feature to attach a delay to - "interjections",
+//! "replyall", "impersonate", and "trigger responses" don't exist here;
+//! every reply is a direct response to a slash command (see `Command` in
+//! [`crate::markov_telegram_bot`]), sent as a plain
+//! `bot.send_message(chat_id, text)` with no reply-threading
+//! (`reply_to_message_id`) to a specific triggering message at all - so
+//! there's neither a real auto-reply call site to delay, nor a way to
+//! detect "the triggering message was deleted in the meantime" the way a
+//! reply-threaded send failing could. [`typing_delay`] is the one piece of
+//! this that's real, useful, and testable on its own regardless: a
+//! humanization delay that scales with how long the reply is, capped so a
+//! long generated message doesn't leave the bot looking stalled.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+/// The shortest a humanization delay can be, before any per-word typing
+/// time is added - even a one-word reply shouldn't come back faster than a
+/// person could plausibly have read the trigger and started typing.
+const MIN_DELAY: Duration = Duration::from_millis(1_000);
+
+/// The most random jitter added on top of [`MIN_DELAY`], so replies don't
+/// all land exactly [`MIN_DELAY`] plus typing time apart.
+const MAX_JITTER: Duration = Duration::from_millis(3_000);
+
+/// Roughly how long a person takes to type one word, for scaling the delay
+/// by how long the reply is.
+const PER_WORD_TYPING_TIME: Duration = Duration::from_millis(200);
+
+/// Hard cap on the total delay, so a long generated message doesn't leave a
+/// reply looking stalled rather than merely human-paced.
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// Computes how long to hold back a reply of `word_count` words before
+/// sending it: [`MIN_DELAY`] plus up to [`MAX_JITTER`] of random jitter,
+/// plus [`PER_WORD_TYPING_TIME`] per word, capped at [`MAX_DELAY`].
+pub fn typing_delay(word_count: usize, rng: &mut impl Rng) -> Duration {
+    let jitter_ms = rng.random_range(0..=MAX_JITTER.as_millis() as u64);
+    let typing_ms = PER_WORD_TYPING_TIME.as_millis() as u64 * word_count as u64;
+    let total_ms = MIN_DELAY.as_millis() as u64 + jitter_ms + typing_ms;
+    Duration::from_millis(total_ms.min(MAX_DELAY.as_millis() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn typing_delay_is_never_shorter_than_the_minimum() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            assert!(typing_delay(0, &mut rng) >= MIN_DELAY);
+        }
+    }
+
+    #[test]
+    fn typing_delay_grows_with_word_count() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let short = typing_delay(1, &mut rng);
+        let long = typing_delay(20, &mut rng);
+        assert!(long > short);
+    }
+
+    #[test]
+    fn typing_delay_never_exceeds_the_cap_even_for_a_very_long_message() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            assert!(typing_delay(10_000, &mut rng) <= MAX_DELAY);
+        }
+    }
+
+    #[test]
+    fn typing_delay_varies_with_jitter_for_the_same_word_count() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let delays: std::collections::HashSet<Duration> = (0..20).map(|_| typing_delay(3, &mut rng)).collect();
+        assert!(delays.len() > 1, "expected jitter to produce more than one distinct delay across attempts");
+    }
+}
@@ -0,0 +1,262 @@
+//! Rolling back a single `/importchat` run: when opted into tracking (see
+//! [`crate::markov_telegram_bot::IMPORT_TRACK_ROLLBACK_FLAG`]), an import
+//! records, per affected key in
+//! [`ChatData::data`](crate::markov_telegram_bot::ChatData::data), the
+//! isolated chain of just what that one import taught it - keyed by the
+//! import's checksum - so a bad import (wrong file, spam-filled) can later
+//! be subtracted back out without touching anything learned before or after
+//! it.
+//!
+//! Tracking is opt-in because a contribution chain roughly doubles the
+//! storage cost of the import it covers; [`expire_stale_contributions`]
+//! sweeps them out after [`CONTRIBUTION_TTL_DAYS`], on the assumption that an
+//! import left standing that long was fine.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::markov_chain::TripletMarkovChain;
+use crate::markov_telegram_bot::{MongoStorage, Storage};
+
+/// How long a tracked contribution is kept before [`expire_stale_contributions`]
+/// drops it.
+pub const CONTRIBUTION_TTL_DAYS: i64 = 30;
+
+/// One chain key's contribution from a single import run, recorded so it can
+/// later be subtracted back out via [`rollback_import`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportContribution {
+    pub chat_id: i64,
+    /// The import's checksum, as computed by
+    /// [`crate::markov_telegram_bot::import_checksum`].
+    pub import_id: String,
+    /// The [`ChatData::data`](crate::markov_telegram_bot::ChatData::data) key
+    /// this contribution applies to - a user ID (as a string) or
+    /// [`crate::markov_telegram_bot::ALL_KEY`].
+    pub key: String,
+    /// Just the transitions this one import taught `key`'s chain, isolated
+    /// from anything learned before or since.
+    pub chain: TripletMarkovChain,
+    pub imported_at_unix: i64,
+}
+
+/// What one rollback did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollbackSummary {
+    pub keys_affected: Vec<String>,
+}
+
+/// Subtracts import `import_id`'s recorded contribution back out of chat
+/// `chat_id`'s stored chains (see [`TripletMarkovChain::subtract`]), then
+/// forgets the contribution records so the same import can't be rolled back
+/// twice. Errors are returned as plain strings for direct display, matching
+/// [`crate::migrate::run_migration`]'s sibling CLI-and-command surface.
+pub async fn rollback_import(storage: &dyn Storage, chat_id: i64, import_id: &str) -> Result<RollbackSummary, String> {
+    let contributions = storage
+        .list_import_contributions(chat_id, import_id)
+        .await
+        .map_err(|err| format!("failed to load the import's recorded contribution: {err}"))?;
+    if contributions.is_empty() {
+        return Err(
+            "no tracked contribution found for that checksum - it may have expired, already been rolled back, or was imported without --track-rollback."
+                .to_string(),
+        );
+    }
+
+    let mut chat_data = storage
+        .read_chat_data(chat_id)
+        .await
+        .map_err(|err| format!("failed to load the chat's chains: {err}"))?
+        .unwrap_or_default();
+
+    let mut summary = RollbackSummary::default();
+    for contribution in &contributions {
+        if let Some(chain) = chat_data.data.get_mut(&contribution.key) {
+            chain.subtract(&contribution.chain);
+            summary.keys_affected.push(contribution.key.clone());
+        }
+    }
+    // The rolled-back words may no longer be unique to whoever else still
+    // has them; rebuilt on demand like every other word-index invalidation.
+    chat_data.word_index = None;
+
+    storage
+        .write_chat_data(chat_id, &chat_data)
+        .await
+        .map_err(|err| format!("failed to save the rolled-back chains: {err}"))?;
+    storage
+        .delete_import_contributions(chat_id, import_id)
+        .await
+        .map_err(|err| format!("rolled back the chains, but failed to clear the tracked contribution: {err}"))?;
+
+    Ok(summary)
+}
+
+/// Sweeps every tracked contribution and deletes any older than
+/// [`CONTRIBUTION_TTL_DAYS`]. Only the tracking record expires - the learned
+/// data itself is untouched, so an expired import simply can no longer be
+/// rolled back. Returns how many were expired.
+pub async fn expire_stale_contributions(storage: &dyn Storage, now_unix: i64) -> usize {
+    let cutoff = now_unix - CONTRIBUTION_TTL_DAYS * 86_400;
+
+    let contributions = match storage.list_all_import_contributions().await {
+        Ok(contributions) => contributions,
+        Err(err) => {
+            log::error!("failed to list import contributions for expiry: {err}");
+            return 0;
+        }
+    };
+
+    let mut stale_imports: Vec<(i64, String)> = contributions
+        .into_iter()
+        .filter(|contribution| contribution.imported_at_unix < cutoff)
+        .map(|contribution| (contribution.chat_id, contribution.import_id))
+        .collect();
+    stale_imports.sort_unstable();
+    stale_imports.dedup();
+
+    let mut expired = 0;
+    for (chat_id, import_id) in stale_imports {
+        if let Err(err) = storage.delete_import_contributions(chat_id, &import_id).await {
+            log::error!("failed to expire import contribution {import_id} for chat {chat_id}: {err}");
+            continue;
+        }
+        expired += 1;
+    }
+    expired
+}
+
+/// Runs the `rollback-import` CLI subcommand against the MongoDB configured
+/// via [`Config::from_env`]: `rollback-import <chat_id> <checksum>`, for
+/// undoing an import from outside the chat it targeted (e.g. after the
+/// checksum was found in logs rather than a live `/importchat` reply).
+pub async fn run_rollback_import_cli(args: &[String]) {
+    let (chat_id, import_id) = match args {
+        [chat_id, import_id] => (chat_id, import_id),
+        _ => {
+            eprintln!("Usage: rollback-import <chat_id> <checksum>");
+            std::process::exit(2);
+        }
+    };
+    let Ok(chat_id) = chat_id.parse::<i64>() else {
+        eprintln!("rollback-import: chat_id must be a whole number");
+        std::process::exit(2);
+    };
+
+    let config = Config::from_env();
+    let storage = match MongoStorage::connect(&config.mongo_uri, &config.mongo_db_name).await {
+        Ok(storage) => storage,
+        Err(err) => {
+            eprintln!("rollback-import: failed to connect to MongoDB: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    match rollback_import(&storage, chat_id, import_id).await {
+        Ok(summary) => {
+            println!("rolled back import {import_id} in chat {chat_id}: {} key(s) affected: {:?}", summary.keys_affected.len(), summary.keys_affected);
+        }
+        Err(err) => {
+            eprintln!("rollback-import: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::markov_telegram_bot::{ChatData, InMemoryStorage};
+    use crate::testing::{assert_transition, ChainBuilder};
+
+    fn contribution(chat_id: i64, import_id: &str, key: &str, chain: TripletMarkovChain, imported_at_unix: i64) -> ImportContribution {
+        ImportContribution { chat_id, import_id: import_id.to_string(), key: key.to_string(), chain, imported_at_unix }
+    }
+
+    #[tokio::test]
+    async fn rollback_subtracts_the_contribution_and_clears_the_record() {
+        let storage = InMemoryStorage::new();
+        let live_chain = ChainBuilder::new().msgs(&["a b c", "a b c", "x y z"]).build();
+        storage.write_chat_data(1, &ChatData { data: HashMap::from([("42".to_string(), live_chain)]), ..Default::default() }).await.unwrap();
+
+        let contributed_chain = ChainBuilder::new().msg("a b c").build();
+        storage
+            .write_import_contribution(&contribution(1, "abc123", "42", contributed_chain, 1_000))
+            .await
+            .unwrap();
+
+        let summary = rollback_import(&storage, 1, "abc123").await.unwrap();
+        assert_eq!(summary.keys_affected, vec!["42".to_string()]);
+
+        let chat_data = storage.read_chat_data(1).await.unwrap().unwrap();
+        let chain = &chat_data.data["42"];
+        assert_transition(chain, "a", "b", "c", 1);
+        assert_transition(chain, "x", "y", "z", 1);
+
+        assert!(storage.list_import_contributions(1, "abc123").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback_never_drives_a_count_negative() {
+        let storage = InMemoryStorage::new();
+        // The live chain has fewer "a b c" than the tracked contribution
+        // claims - e.g. some were pruned, or a partial rollback already ran.
+        let live_chain = ChainBuilder::new().msg("a b c").build();
+        storage.write_chat_data(1, &ChatData { data: HashMap::from([("42".to_string(), live_chain)]), ..Default::default() }).await.unwrap();
+
+        let contributed_chain = ChainBuilder::new().weighted_msg("a b c", 5).build();
+        storage
+            .write_import_contribution(&contribution(1, "abc123", "42", contributed_chain, 1_000))
+            .await
+            .unwrap();
+
+        rollback_import(&storage, 1, "abc123").await.unwrap();
+
+        let chat_data = storage.read_chat_data(1).await.unwrap().unwrap();
+        assert_transition(&chat_data.data["42"], "a", "b", "c", 0);
+    }
+
+    #[tokio::test]
+    async fn rollback_with_an_unknown_checksum_errors() {
+        let storage = InMemoryStorage::new();
+        let err = rollback_import(&storage, 1, "nonexistent").await.unwrap_err();
+        assert!(err.contains("no tracked contribution"));
+    }
+
+    #[tokio::test]
+    async fn rolling_back_the_same_import_twice_errors_the_second_time() {
+        let storage = InMemoryStorage::new();
+        storage.write_chat_data(1, &ChatData { data: HashMap::from([("42".to_string(), ChainBuilder::new().msg("a b c").build())]), ..Default::default() }).await.unwrap();
+        storage
+            .write_import_contribution(&contribution(1, "abc123", "42", ChainBuilder::new().msg("a b c").build(), 1_000))
+            .await
+            .unwrap();
+
+        assert!(rollback_import(&storage, 1, "abc123").await.is_ok());
+        assert!(rollback_import(&storage, 1, "abc123").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expiry_drops_contributions_older_than_the_ttl_but_leaves_recent_ones() {
+        let storage = InMemoryStorage::new();
+        let now = 100 * 86_400;
+        let stale_at = now - (CONTRIBUTION_TTL_DAYS + 1) * 86_400;
+        let fresh_at = now - (CONTRIBUTION_TTL_DAYS - 1) * 86_400;
+
+        storage
+            .write_import_contribution(&contribution(1, "stale", "42", ChainBuilder::new().msg("a b c").build(), stale_at))
+            .await
+            .unwrap();
+        storage
+            .write_import_contribution(&contribution(1, "fresh", "42", ChainBuilder::new().msg("a b c").build(), fresh_at))
+            .await
+            .unwrap();
+
+        let expired = expire_stale_contributions(&storage, now).await;
+        assert_eq!(expired, 1);
+        assert!(storage.list_import_contributions(1, "stale").await.unwrap().is_empty());
+        assert!(!storage.list_import_contributions(1, "fresh").await.unwrap().is_empty());
+    }
+}
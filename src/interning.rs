@@ -0,0 +1,114 @@
+//! A generic string interner: a word table plus a reverse lookup, so
+//! repeated strings can be stored once and referenced by a small `u32` id
+//! instead of many owned [`String`] copies.
+//!
+//! Not wired into [`crate::markov_chain::TripletMarkovChain`]. That struct's
+//! in-memory shape *is* its serialized shape - a recursive `ChainNode` tree,
+//! string-keyed all the way down, whose depth is the chat's configured
+//! `order` (see [`crate::markov_chain`]'s module docs) - not a flat
+//! `(pair_idx, third_idx) -> count` table. Retrofitting interned ids through
+//! that recursion while still producing byte-identical documents for every
+//! chat already stored (the whole point of
+//! [`crate::markov_telegram_bot::encode_db_field_names`] and the legacy
+//! fallback in [`crate::markov_telegram_bot::parse_chat_chains`]) is a
+//! rewrite of the module's storage layer, not an incremental addition on top
+//! of it. [`StringInterner`] is left here as a real, tested building block
+//! for that future rewrite, rather than a partially-wired stand-in for it.
+
+use std::collections::HashMap;
+
+/// Interns strings into small `u32` ids, deduplicating repeats. Ids are
+/// assigned in first-seen order and are stable for the lifetime of this
+/// interner, but are not meaningful across different instances.
+#[derive(Debug, Clone, Default)]
+pub struct StringInterner {
+    words: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl StringInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `word`'s id, assigning it the next id if this is the first
+    /// time it's been seen.
+    pub fn intern(&mut self, word: &str) -> u32 {
+        if let Some(&id) = self.ids.get(word) {
+            return id;
+        }
+        let id = self.words.len() as u32;
+        self.words.push(word.to_string());
+        self.ids.insert(word.to_string(), id);
+        id
+    }
+
+    /// Looks up `word`'s id without interning it.
+    pub fn get(&self, word: &str) -> Option<u32> {
+        self.ids.get(word).copied()
+    }
+
+    /// Resolves `id` back to the string it was interned from, or `None` if
+    /// no word has that id in this interner.
+    pub fn resolve(&self, id: u32) -> Option<&str> {
+        self.words.get(id as usize).map(String::as_str)
+    }
+
+    /// The number of distinct words interned so far.
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning_the_same_word_twice_returns_the_same_id() {
+        let mut interner = StringInterner::new();
+        let first = interner.intern("hello");
+        let second = interner.intern("hello");
+        assert_eq!(first, second);
+        assert_eq!(interner.len(), 1);
+    }
+
+    #[test]
+    fn distinct_words_get_distinct_ids() {
+        let mut interner = StringInterner::new();
+        let a = interner.intern("a");
+        let b = interner.intern("b");
+        assert_ne!(a, b);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn resolve_round_trips_an_interned_word() {
+        let mut interner = StringInterner::new();
+        let id = interner.intern("round-trip");
+        assert_eq!(interner.resolve(id), Some("round-trip"));
+    }
+
+    #[test]
+    fn get_returns_none_for_a_word_never_interned() {
+        let interner = StringInterner::new();
+        assert_eq!(interner.get("never seen"), None);
+    }
+
+    #[test]
+    fn resolve_returns_none_for_an_id_never_assigned() {
+        let interner = StringInterner::new();
+        assert_eq!(interner.resolve(0), None);
+    }
+
+    #[test]
+    fn a_fresh_interner_is_empty() {
+        let interner = StringInterner::new();
+        assert!(interner.is_empty());
+        assert_eq!(interner.len(), 0);
+    }
+}
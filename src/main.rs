@@ -0,0 +1,568 @@
+mod auto_prune;
+mod config;
+mod dead_letter;
+mod delete_confirmation;
+mod gc_users;
+mod health;
+#[cfg(test)]
+mod humanize;
+mod import_rollback;
+#[cfg(test)]
+mod interning;
+mod markov_chain;
+mod markov_telegram_bot;
+mod migrate;
+mod pagination;
+mod perf;
+mod polling;
+mod preload;
+mod presentation;
+mod quarantine;
+mod redaction;
+mod replay;
+mod scheduler;
+mod stats_export;
+mod templates;
+mod theme;
+#[cfg(test)]
+mod testing;
+mod tokenizer;
+mod user_prefs;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use teloxide::prelude::*;
+
+use config::Config;
+use dead_letter::DeadLetterQueue;
+use delete_confirmation::DeleteConfirmations;
+use health::HealthState;
+use markov_chain::{DefaultChainOrder, DEFAULT_ORDER};
+use markov_telegram_bot::{recover_pending_learns, MongoStorage, SentMessageTracker, Storage};
+use perf::PerfTracker;
+use polling::PollFeatures;
+use quarantine::{BurstDetector, QuarantineBuffer};
+use scheduler::Scheduler;
+use stats_export::{ActivityCounters, ChatStatsRow};
+
+/// How often the maintenance schedule checks whether it's time to export
+/// stats. Exports themselves are still once-per-day, gated by
+/// [`append_chat_stats_row`](stats_export::append_chat_stats_row)'s
+/// idempotence.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the auto-prune schedule checks each opted-in chat's chain size.
+const AUTO_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the learn journal is swept for entries left behind by a crash or
+/// a storage failure. Also run once at startup, so a crash is recovered from
+/// promptly rather than waiting out the first interval.
+const LEARN_JOURNAL_RECOVERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often the dead-letter queue's retry schedule checks whether storage is
+/// reachable again. Checking this often (rather than truly backing off) is a
+/// deliberate simplification: a failed [`Storage::ping`] is cheap, and it
+/// keeps this schedule the same shape as the bot's other maintenance loops.
+const DEAD_LETTER_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often stale tracked import contributions (see [`import_rollback`]) are
+/// swept out. Run far less often than the other maintenance schedules, since
+/// contributions only expire on the order of days, not hours.
+const IMPORT_CONTRIBUTION_EXPIRY_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often [`run_gc_users_schedule`] sweeps `user_infos` for rows whose
+/// chain is gone. Runs far less often than the other maintenance schedules,
+/// since a stale row is harmless clutter rather than something that affects
+/// bot behavior.
+const GC_USERS_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How many of the busiest chats get their data preloaded at startup. See
+/// [`preload`].
+const PRELOAD_TOP_K: usize = 20;
+
+/// How many chats [`preload::preload_top_chats`] reads concurrently at
+/// startup.
+const PRELOAD_CONCURRENCY: usize = 4;
+
+/// How long startup preloading is allowed to run before the dispatcher
+/// starts polling regardless of what's still in flight.
+const PRELOAD_TIME_BUDGET: Duration = Duration::from_secs(10);
+
+/// Disables startup preloading entirely, e.g. for a low-memory deployment
+/// where warming every busy chat's chain up front isn't worth the RAM.
+const NO_PRELOAD_FLAG: &str = "--no-preload";
+
+/// Sets the chain order (see [`markov_chain::DEFAULT_ORDER`]) new chats are
+/// created at, e.g. `--order 2` for shorter-context, more chaotic
+/// generations. Takes the following argument as its value; falls back to
+/// [`DEFAULT_ORDER`] if absent, or unparsable. Existing chats are unaffected
+/// regardless of this flag - see [`markov_telegram_bot::learn_into_with_order`].
+const ORDER_FLAG: &str = "--order";
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("replay") {
+        replay::run_replay_cli(&args[2..]).await;
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        migrate::run_migrate_cli().await;
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("rollback-import") {
+        import_rollback::run_rollback_import_cli(&args[2..]).await;
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("gc-users") {
+        gc_users::run_gc_users_cli(&args[2..]).await;
+        return;
+    }
+
+    init_logger();
+    log::info!("starting markov-telegram-bot-rs...");
+
+    let config = Config::from_env();
+    let storage: Arc<dyn Storage> = Arc::new(
+        MongoStorage::connect(&config.mongo_uri, &config.mongo_db_name)
+            .await
+            .expect("failed to connect to MongoDB"),
+    );
+    let activity_counters = Arc::new(ActivityCounters::new());
+    let health = Arc::new(HealthState::new(chrono::Utc::now().timestamp()));
+    health.set_bot_count(config.bot_tokens.len());
+
+    if let Some(dir) = config.stats_export_dir.clone() {
+        tokio::spawn(run_stats_export_schedule(
+            Arc::clone(&storage),
+            Arc::clone(&activity_counters),
+            PathBuf::from(dir),
+            Arc::clone(&health),
+        ));
+    }
+
+    // Every bot token gets its own `Bot` client and polling loop below, but
+    // shares everything else in this function: storage, caches, the
+    // scheduler, and health state. That sharing is what makes `/status` and
+    // health checks aggregate across bots "for free" - there's only one
+    // `HealthState`/`Scheduler` for the whole process to report on,
+    // regardless of how many bots are polling into it - and why a
+    // `enable_ctrlc_handler()`'d Ctrl+C shuts every bot's dispatcher down
+    // together (each installs its own listener on the same signal).
+    //
+    // The bot for `run_auto_prune_schedule`'s admin notifications is always
+    // the first configured token: that schedule sweeps every chat in
+    // storage regardless of which bot is a member of it, and fully routing
+    // its notification through whichever bot actually owns a given chat
+    // (see [`markov_telegram_bot::ChatData::owner_bot_id`]) is left for a
+    // follow-up, since it'd mean threading bot selection through every
+    // storage-wide maintenance sweep, not just this one.
+    let bots: Vec<Bot> = config.bot_tokens.iter().map(Bot::new).collect();
+    let primary_bot = bots[0].clone();
+
+    let sent_tracker = Arc::new(SentMessageTracker::new());
+    let perf_tracker = Arc::new(PerfTracker::new());
+    let owner_id = Arc::new(config.owner_user_id.map(|id| UserId(id as u64)));
+    let burst_detector = Arc::new(BurstDetector::new());
+    let quarantine_buffer = Arc::new(QuarantineBuffer::new());
+    let dead_letter = Arc::new(DeadLetterQueue::new());
+    let delete_confirmations = Arc::new(DeleteConfirmations::new());
+    let scheduler = Arc::new(Scheduler::new());
+
+    tokio::spawn(run_auto_prune_schedule(Arc::clone(&storage), primary_bot, Arc::clone(&health)));
+
+    let recovered = recover_pending_learns(storage.as_ref()).await;
+    if recovered > 0 {
+        log::info!("recovered {recovered} pending learn(s) from the journal at startup");
+    }
+    update_pending_write_buffer_depth(storage.as_ref(), &health).await;
+    tokio::spawn(run_learn_journal_recovery_schedule(Arc::clone(&storage), Arc::clone(&health)));
+    tokio::spawn(run_dead_letter_retry_schedule(Arc::clone(&storage), Arc::clone(&dead_letter), Arc::clone(&health)));
+    tokio::spawn(run_import_contribution_expiry_schedule(Arc::clone(&storage), Arc::clone(&scheduler)));
+    tokio::spawn(run_gc_users_schedule(Arc::clone(&storage), Arc::clone(&scheduler)));
+
+    let default_chain_order = Arc::new(DefaultChainOrder(parse_order_flag(&args)));
+    log::info!("new chats will be created at chain order {}", default_chain_order.0);
+
+    if args.iter().any(|arg| arg == NO_PRELOAD_FLAG) {
+        log::info!("startup preload skipped ({NO_PRELOAD_FLAG})");
+    } else {
+        let cache = preload::ChatDataCache::new();
+        let report = preload::preload_top_chats(storage.as_ref(), &cache, PRELOAD_TOP_K, PRELOAD_CONCURRENCY, PRELOAD_TIME_BUDGET).await;
+        log::info!(
+            "startup preload: {}/{} selected chat(s) loaded into a {}-entry cache{}",
+            report.preloaded.len(),
+            report.selected.len(),
+            cache.len(),
+            if report.timed_out { " (time budget exhausted)" } else { "" }
+        );
+    }
+
+    let poll_features = PollFeatures {
+        inline_queries: config.enable_inline_queries,
+        callback_queries: config.enable_callback_queries,
+        chat_membership_changes: config.enable_chat_membership_updates,
+        channel_posts: config.enable_channel_posts,
+    };
+
+    let dispatch_tasks: Vec<_> = bots
+        .into_iter()
+        .map(|bot| {
+            tokio::spawn(run_bot_dispatcher(
+                bot,
+                Arc::clone(&storage),
+                Arc::clone(&activity_counters),
+                Arc::clone(&sent_tracker),
+                Arc::clone(&perf_tracker),
+                Arc::clone(&owner_id),
+                Arc::clone(&burst_detector),
+                Arc::clone(&quarantine_buffer),
+                Arc::clone(&health),
+                Arc::clone(&dead_letter),
+                Arc::clone(&delete_confirmations),
+                Arc::clone(&default_chain_order),
+                Arc::clone(&scheduler),
+                poll_features,
+                config.poll_timeout_secs,
+                config.poll_limit,
+            ))
+        })
+        .collect();
+
+    for task in dispatch_tasks {
+        if let Err(err) = task.await {
+            log::error!("a bot's dispatcher task panicked: {err}");
+        }
+    }
+
+    let flushed = dead_letter.retry_all(storage.as_ref()).await;
+    if flushed > 0 {
+        log::info!("flushed {flushed} dead-lettered learn(s) on shutdown");
+    }
+    let remaining = dead_letter.depth();
+    if remaining > 0 {
+        log::error!("shutting down with {remaining} dead-lettered learn(s) still unflushed");
+    }
+}
+
+/// Runs one bot's `getMe`, polling loop, and dispatcher to completion (i.e.
+/// until its `enable_ctrlc_handler()` fires), sharing every dependency
+/// except `bot` itself with every other bot this process is running - see
+/// the module-level comment in [`main`] on what that sharing buys.
+#[allow(clippy::too_many_arguments)]
+async fn run_bot_dispatcher(
+    bot: Bot,
+    storage: Arc<dyn Storage>,
+    activity_counters: Arc<ActivityCounters>,
+    sent_tracker: Arc<SentMessageTracker>,
+    perf_tracker: Arc<PerfTracker>,
+    owner_id: Arc<Option<UserId>>,
+    burst_detector: Arc<BurstDetector>,
+    quarantine_buffer: Arc<QuarantineBuffer>,
+    health: Arc<HealthState>,
+    dead_letter: Arc<DeadLetterQueue>,
+    delete_confirmations: Arc<DeleteConfirmations>,
+    default_chain_order: Arc<DefaultChainOrder>,
+    scheduler: Arc<Scheduler>,
+    poll_features: PollFeatures,
+    poll_timeout_secs: u64,
+    poll_limit: u8,
+) {
+    let bot_id = Arc::new(bot.get_me().await.expect("failed to fetch the bot's own user info").id);
+
+    let update_listener = teloxide::update_listeners::Polling::builder(bot.clone())
+        .timeout(Duration::from_secs(poll_timeout_secs))
+        .limit(poll_limit)
+        .allowed_updates(polling::allowed_updates(poll_features))
+        .delete_webhook()
+        .await
+        .build();
+
+    Dispatcher::builder(bot, markov_telegram_bot::handler())
+        .dependencies(dptree::deps![
+            storage,
+            activity_counters,
+            sent_tracker,
+            bot_id,
+            perf_tracker,
+            owner_id,
+            burst_detector,
+            quarantine_buffer,
+            health,
+            dead_letter,
+            delete_confirmations,
+            default_chain_order,
+            scheduler
+        ])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch_with_listener(update_listener, LoggingErrorHandler::with_custom_text("An error from the update listener"))
+        .await;
+}
+
+/// Parses [`ORDER_FLAG`]'s value out of `args`, falling back to
+/// [`DEFAULT_ORDER`] if the flag is absent, has no following argument, or
+/// that argument doesn't parse as a `usize`.
+fn parse_order_flag(args: &[String]) -> usize {
+    args.iter()
+        .position(|arg| arg == ORDER_FLAG)
+        .and_then(|index| args.get(index + 1))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_ORDER)
+}
+
+/// Periodically exports a stats CSV row for every known chat. Safe to run
+/// more often than once a day, since [`stats_export::append_chat_stats_row`]
+/// skips chats that already have a row for today.
+async fn run_stats_export_schedule(
+    storage: Arc<dyn Storage>,
+    activity_counters: Arc<ActivityCounters>,
+    dir: PathBuf,
+    health: Arc<HealthState>,
+) {
+    let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let chat_ids = match storage.list_chat_ids().await {
+            Ok(ids) => ids,
+            Err(err) => {
+                log::error!("failed to list chats for stats export: {err}");
+                health.record_storage_error(err);
+                continue;
+            }
+        };
+
+        for chat_id in chat_ids {
+            if let Err(err) = export_chat_stats(storage.as_ref(), &activity_counters, &dir, chat_id, &date).await {
+                log::error!("failed to export stats for chat {chat_id}: {err}");
+                health.record_storage_error(err);
+            }
+        }
+    }
+}
+
+async fn export_chat_stats(
+    storage: &dyn Storage,
+    activity_counters: &ActivityCounters,
+    dir: &std::path::Path,
+    chat_id: i64,
+    date: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(chat_data) = storage.read_chat_data(chat_id).await? else {
+        return Ok(());
+    };
+    let messages_learned = activity_counters.take_count(chat_id);
+    let row = ChatStatsRow::from_chat_data(date, chat_id, &chat_data, messages_learned);
+    stats_export::append_chat_stats_row(dir, &row)?;
+    Ok(())
+}
+
+/// Periodically checks every chat that has opted into automatic pruning and,
+/// if its stored chain has grown past the configured cap, prunes it back
+/// down. There's no admin-roster concept yet, so notifications are sent to
+/// the chat itself rather than to specific admins, and the audit log is
+/// just [`log`] output.
+async fn run_auto_prune_schedule(storage: Arc<dyn Storage>, bot: Bot, health: Arc<HealthState>) {
+    let mut interval = tokio::time::interval(AUTO_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let chat_ids = match storage.list_chat_ids().await {
+            Ok(ids) => ids,
+            Err(err) => {
+                log::error!("failed to list chats for auto-prune: {err}");
+                health.record_storage_error(err);
+                continue;
+            }
+        };
+
+        for chat_id in chat_ids {
+            if let Err(err) = auto_prune_chat(storage.as_ref(), &bot, chat_id).await {
+                log::error!("failed to auto-prune chat {chat_id}: {err}");
+                health.record_storage_error(err);
+            }
+        }
+    }
+}
+
+/// Re-reads the pending learn journal's current size and records it on
+/// `health`, so `/status` reflects how deep the write buffer is without
+/// having to hook every [`markov_telegram_bot::learn_with_journal`] call.
+async fn update_pending_write_buffer_depth(storage: &dyn Storage, health: &HealthState) {
+    match storage.list_pending_learns().await {
+        Ok(pending) => health.set_pending_write_buffer_depth(pending.len()),
+        Err(err) => {
+            log::error!("failed to list pending learns for health reporting: {err}");
+            health.record_storage_error(err);
+        }
+    }
+}
+
+async fn auto_prune_chat(storage: &dyn Storage, bot: &Bot, chat_id: i64) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = storage.get_chat_settings(chat_id).await?;
+    if !settings.auto_prune_enabled() {
+        return Ok(());
+    }
+    let (Some(min_count), Some(max_kb)) = (settings.auto_prune_min_count, settings.auto_prune_max_document_kb) else {
+        return Ok(());
+    };
+
+    let Some(mut chat_data) = storage.read_chat_data(chat_id).await? else {
+        return Ok(());
+    };
+
+    let mut reports = Vec::new();
+    for (key, chain) in chat_data.data.iter_mut() {
+        if let Some(report) = auto_prune::auto_prune(chain, min_count, max_kb) {
+            reports.push((key.clone(), report));
+        }
+    }
+    if reports.is_empty() {
+        return Ok(());
+    }
+
+    storage.write_chat_data(chat_id, &chat_data).await?;
+
+    let mut summary = String::new();
+    for (key, report) in &reports {
+        log::info!(
+            "auto-pruned chat {chat_id} chain {key}: removed {} transitions, thresholds {:?}, now {} KB",
+            report.transitions_removed,
+            report.thresholds_applied,
+            report.final_size_kb,
+        );
+        summary.push_str(&format!(
+            "- {key}: removed {} transitions, now {} KB\n",
+            report.transitions_removed, report.final_size_kb,
+        ));
+    }
+    bot.send_message(ChatId(chat_id), format!("Automatic pruning ran for this chat:\n{summary}"))
+        .await?;
+
+    Ok(())
+}
+
+/// Periodically re-applies any learn operations left behind in the journal by
+/// a crash or a storage failure. Also run once at startup; see
+/// [`recover_pending_learns`].
+async fn run_learn_journal_recovery_schedule(storage: Arc<dyn Storage>, health: Arc<HealthState>) {
+    let mut interval = tokio::time::interval(LEARN_JOURNAL_RECOVERY_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let recovered = recover_pending_learns(storage.as_ref()).await;
+        if recovered > 0 {
+            log::info!("recovered {recovered} pending learn(s) from the journal");
+        }
+        update_pending_write_buffer_depth(storage.as_ref(), &health).await;
+    }
+}
+
+/// Periodically retries every learn queued in `dead_letter`, gated on
+/// [`Storage::ping`] so a still-down database isn't hammered with retries
+/// that are certain to fail. Also drops entries that have aged out, and
+/// records the queue's current depth on `health`.
+async fn run_dead_letter_retry_schedule(storage: Arc<dyn Storage>, dead_letter: Arc<DeadLetterQueue>, health: Arc<HealthState>) {
+    let mut interval = tokio::time::interval(DEAD_LETTER_RETRY_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        dead_letter.drain_expired(chrono::Utc::now().timestamp());
+        health.set_cache_size("dead_letter_queue", dead_letter.depth());
+
+        if dead_letter.depth() == 0 {
+            continue;
+        }
+        if let Err(err) = storage.ping().await {
+            log::warn!("skipping dead-letter retry, storage still unreachable: {err}");
+            continue;
+        }
+
+        let retried = dead_letter.retry_all(storage.as_ref()).await;
+        if retried > 0 {
+            log::info!("retried {retried} dead-lettered learn(s) after storage recovered");
+        }
+        health.set_cache_size("dead_letter_queue", dead_letter.depth());
+    }
+}
+
+/// Periodically drops tracked import contributions old enough that
+/// [`import_rollback::CONTRIBUTION_TTL_DAYS`] has elapsed. Doesn't touch the
+/// chains those imports contributed to - only the bookkeeping that would
+/// otherwise let them still be rolled back. Routed through [`Scheduler`] so a
+/// restart doesn't wait a full fresh [`IMPORT_CONTRIBUTION_EXPIRY_INTERVAL`]
+/// before its first post-restart sweep.
+async fn run_import_contribution_expiry_schedule(storage: Arc<dyn Storage>, scheduler: Arc<Scheduler>) {
+    let mut interval = tokio::time::interval(IMPORT_CONTRIBUTION_EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let now = chrono::Utc::now().timestamp();
+        let storage_ref = storage.as_ref();
+        scheduler
+            .run_job(storage_ref, "import_contribution_expiry", IMPORT_CONTRIBUTION_EXPIRY_INTERVAL, now, || async move {
+                let expired = import_rollback::expire_stale_contributions(storage_ref, now).await;
+                if expired > 0 {
+                    log::info!("expired {expired} stale tracked import contribution(s)");
+                }
+                Ok(())
+            })
+            .await;
+    }
+}
+
+/// Periodically deletes `user_infos` rows old enough and chainless enough to
+/// meet [`gc_users::DEFAULT_MAX_AGE_SECS`]. The `gc-users` CLI subcommand
+/// covers ad-hoc runs with a custom `--max-age`; this schedule is what keeps
+/// the collection bounded without an operator remembering to run it. Routed
+/// through [`Scheduler`] so a restart doesn't wait a full fresh
+/// [`GC_USERS_INTERVAL`] before its first post-restart sweep.
+async fn run_gc_users_schedule(storage: Arc<dyn Storage>, scheduler: Arc<Scheduler>) {
+    let mut interval = tokio::time::interval(GC_USERS_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let now = chrono::Utc::now().timestamp();
+        let storage_ref = storage.as_ref();
+        scheduler
+            .run_job(storage_ref, "gc_users", GC_USERS_INTERVAL, now, || async move {
+                let summary = gc_users::run_gc(storage_ref, gc_users::DEFAULT_MAX_AGE_SECS, now).await;
+                if !summary.deleted.is_empty() {
+                    log::info!(
+                        "gc-users: deleted {} stale user_infos row(s) out of {} scanned",
+                        summary.deleted.len(),
+                        summary.rows_scanned
+                    );
+                }
+                Ok(())
+            })
+            .await;
+    }
+}
+
+/// Installs a minimal logger that writes to stderr, so we don't need an extra
+/// dependency just to see log output.
+fn init_logger() {
+    struct StderrLogger;
+
+    impl log::Log for StderrLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                eprintln!("[{}] {}", record.level(), record.args());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    log::set_boxed_logger(Box::new(StderrLogger)).expect("failed to install logger");
+    log::set_max_level(log::LevelFilter::Info);
+}
@@ -0,0 +1,121 @@
+//! A `migrate` CLI subcommand: scans every chat and permanently upgrades any
+//! whose stored chain is still in the legacy pair-based format (see
+//! [`crate::markov_chain::TripletMarkovChain::from_legacy_pairs`]), so
+//! future reads no longer need that tolerant, degraded approximation.
+
+use crate::config::Config;
+use crate::markov_telegram_bot::{MongoStorage, Storage};
+
+/// What one migration pass did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationSummary {
+    pub chats_scanned: usize,
+    pub migrated_chat_ids: Vec<i64>,
+}
+
+/// Scans every chat `storage` knows about and, for any whose chain was
+/// tolerantly upcast from the legacy pair-based format on read (see
+/// [`crate::markov_telegram_bot::ChatData::migrated_from_legacy`]), writes
+/// the upcast [`crate::markov_chain::TripletMarkovChain`] back so the chat no
+/// longer needs upcasting on every future read.
+pub async fn run_migration(storage: &dyn Storage) -> MigrationSummary {
+    let mut summary = MigrationSummary::default();
+
+    let chat_ids = match storage.list_chat_ids().await {
+        Ok(ids) => ids,
+        Err(err) => {
+            log::error!("migrate: failed to list chats: {err}");
+            return summary;
+        }
+    };
+    summary.chats_scanned = chat_ids.len();
+
+    for chat_id in chat_ids {
+        let mut chat_data = match storage.read_chat_data(chat_id).await {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(err) => {
+                log::error!("migrate: failed to read chat {chat_id}: {err}");
+                continue;
+            }
+        };
+        if !chat_data.migrated_from_legacy {
+            continue;
+        }
+        chat_data.migrated_from_legacy = false;
+
+        if let Err(err) = storage.write_chat_data(chat_id, &chat_data).await {
+            log::error!("migrate: failed to write back upgraded chat {chat_id}: {err}");
+            continue;
+        }
+        log::info!("migrate: upgraded chat {chat_id} from a legacy pair-based chain");
+        summary.migrated_chat_ids.push(chat_id);
+    }
+
+    summary
+}
+
+/// Runs the `migrate` subcommand against the MongoDB configured via
+/// [`Config::from_env`]. Exits the process with a nonzero code on a
+/// connection failure, so it can be wired into a deploy step.
+pub async fn run_migrate_cli() {
+    let config = Config::from_env();
+    let storage = match MongoStorage::connect(&config.mongo_uri, &config.mongo_db_name).await {
+        Ok(storage) => storage,
+        Err(err) => {
+            eprintln!("migrate: failed to connect to MongoDB: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    let summary = run_migration(&storage).await;
+    println!(
+        "scanned {} chat(s), upgraded {} legacy chain(s): {:?}",
+        summary.chats_scanned,
+        summary.migrated_chat_ids.len(),
+        summary.migrated_chat_ids
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::markov_chain::TripletMarkovChain;
+    use crate::markov_telegram_bot::{ChatData, InMemoryStorage};
+
+    #[tokio::test]
+    async fn migration_writes_back_only_chats_flagged_as_legacy() {
+        let storage = InMemoryStorage::new();
+
+        let mut native_chain = TripletMarkovChain::new();
+        native_chain.add_message("hello world");
+        let native = ChatData { data: HashMap::from([("all".to_string(), native_chain)]), ..Default::default() };
+        storage.write_chat_data(1, &native).await.unwrap();
+
+        let mut legacy_chain = TripletMarkovChain::new();
+        legacy_chain.add_message("hello world");
+        let legacy = ChatData {
+            data: HashMap::from([("all".to_string(), legacy_chain)]),
+            migrated_from_legacy: true,
+            ..Default::default()
+        };
+        storage.write_chat_data(2, &legacy).await.unwrap();
+
+        let summary = run_migration(&storage).await;
+        assert_eq!(summary.chats_scanned, 2);
+        assert_eq!(summary.migrated_chat_ids, vec![2]);
+
+        // The migrated chat's flag doesn't linger after being written back.
+        let reread = storage.read_chat_data(2).await.unwrap().unwrap();
+        assert!(!reread.migrated_from_legacy);
+    }
+
+    #[tokio::test]
+    async fn migration_on_an_empty_storage_scans_nothing() {
+        let storage = InMemoryStorage::new();
+        let summary = run_migration(&storage).await;
+        assert_eq!(summary, MigrationSummary::default());
+    }
+}
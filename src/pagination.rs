@@ -0,0 +1,227 @@
+//! Shared pagination for list-style commands (`/seeds`, `/nextwords`,
+//! `/topwords`) whose output can run to hundreds of words: results are
+//! rendered [`PAGE_SIZE`] at a time, with "◀ ▶" inline buttons that page
+//! through them by editing the original message in place.
+//!
+//! Page state lives entirely in the button's callback data rather than in a
+//! server-side session table, since Telegram caps callback data at 64 bytes
+//! anyway and a session table would need its own expiry sweep: [`PageToken::encode`]
+//! packs the command, its target word (if any), the requested offset, who's
+//! allowed to press it, and when the token was issued, into one compact
+//! string, and [`PageToken::decode`] is the only thing a button press needs
+//! to reconstruct that page. [`PageToken::is_expired`] rejects a press
+//! against a token older than [`PAGE_TOKEN_TTL_SECS`]; the caller is expected
+//! to also check `issuer_user_id` against the pressing user, since a
+//! callback press isn't otherwise scoped to whoever the buttons were sent to.
+
+/// How many list items are rendered per page.
+pub const PAGE_SIZE: usize = 10;
+
+/// How long a page button stays valid after being sent, checked by
+/// [`PageToken::is_expired`].
+pub const PAGE_TOKEN_TTL_SECS: i64 = 600;
+
+/// Telegram's hard cap on a callback button's `data` payload, in bytes.
+pub const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+/// The prefix every encoded [`PageToken`] carries, so
+/// `crate::markov_telegram_bot::handle_callback_query` can route a press to
+/// [`PageToken::decode`] the same way it does for the bot's other callback
+/// flows.
+pub const CALLBACK_DATA_PREFIX: &str = "pg:";
+
+/// The list-style commands pagination is shared across.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagedCommand {
+    Seeds,
+    NextWords,
+    TopWords,
+}
+
+impl PagedCommand {
+    fn code(self) -> char {
+        match self {
+            PagedCommand::Seeds => 's',
+            PagedCommand::NextWords => 'n',
+            PagedCommand::TopWords => 't',
+        }
+    }
+
+    fn from_code(code: char) -> Option<Self> {
+        match code {
+            's' => Some(PagedCommand::Seeds),
+            'n' => Some(PagedCommand::NextWords),
+            't' => Some(PagedCommand::TopWords),
+            _ => None,
+        }
+    }
+}
+
+/// A page-button's callback data, decoded (or about to be encoded). See the
+/// module docs for why every field the button press needs travels in the
+/// data itself rather than in server-side state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageToken {
+    pub command: PagedCommand,
+    /// The word `/nextwords` was run against; empty for `/seeds` and
+    /// `/topwords`, which have no target.
+    pub target: String,
+    pub offset: usize,
+    pub issuer_user_id: i64,
+    pub issued_at_unix: i64,
+}
+
+impl PageToken {
+    /// Encodes this token as callback data, or `None` if `target` is long
+    /// enough to push the result past [`MAX_CALLBACK_DATA_LEN`] - in
+    /// practice this only happens for pathologically long "words" (e.g. a
+    /// pasted URL learned as a single token), so the caller's fallback is
+    /// simply to omit the page buttons rather than error out.
+    pub fn encode(&self) -> Option<String> {
+        let data =
+            format!("{CALLBACK_DATA_PREFIX}{}:{}:{}:{}:{}", self.command.code(), self.offset, self.issuer_user_id, self.issued_at_unix, self.target);
+        (data.len() <= MAX_CALLBACK_DATA_LEN).then_some(data)
+    }
+
+    /// Decodes a page button's callback data, or `None` if it isn't one (or
+    /// is malformed).
+    pub fn decode(data: &str) -> Option<Self> {
+        let rest = data.strip_prefix(CALLBACK_DATA_PREFIX)?;
+        let mut parts = rest.splitn(5, ':');
+        let command = PagedCommand::from_code(parts.next()?.chars().next()?)?;
+        let offset = parts.next()?.parse().ok()?;
+        let issuer_user_id = parts.next()?.parse().ok()?;
+        let issued_at_unix = parts.next()?.parse().ok()?;
+        let target = parts.next()?.to_string();
+        Some(Self { command, target, offset, issuer_user_id, issued_at_unix })
+    }
+
+    /// Whether this token is too old to honor, as of `now_unix`.
+    pub fn is_expired(&self, now_unix: i64) -> bool {
+        now_unix.saturating_sub(self.issued_at_unix) > PAGE_TOKEN_TTL_SECS
+    }
+}
+
+/// One page's worth of `items`, plus whether there's a page before or after
+/// it, for the caller to decide which nav buttons to show.
+pub struct PageView<'a> {
+    pub items: &'a [String],
+    pub offset: usize,
+    pub has_prev: bool,
+    pub has_next: bool,
+}
+
+/// Slices `items` into the [`PAGE_SIZE`]-item page starting at `offset`,
+/// clamping `offset` down to the last valid page start if it's past the end
+/// (e.g. because the underlying data shrank between when a button was sent
+/// and when it was pressed).
+pub fn paginate(items: &[String], offset: usize) -> PageView<'_> {
+    let last_page_offset = if items.is_empty() { 0 } else { (items.len() - 1) / PAGE_SIZE * PAGE_SIZE };
+    let offset = offset.min(last_page_offset);
+    let end = (offset + PAGE_SIZE).min(items.len());
+    PageView { items: &items[offset..end], offset, has_prev: offset > 0, has_next: end < items.len() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(n: usize) -> Vec<String> {
+        (0..n).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn paginate_returns_the_first_page_with_no_prev_and_a_next() {
+        let items = items(25);
+        let view = paginate(&items, 0);
+        assert_eq!(view.items, &items[0..10]);
+        assert_eq!(view.offset, 0);
+        assert!(!view.has_prev);
+        assert!(view.has_next);
+    }
+
+    #[test]
+    fn paginate_returns_a_middle_page_with_both_directions() {
+        let items = items(25);
+        let view = paginate(&items, 10);
+        assert_eq!(view.items, &items[10..20]);
+        assert!(view.has_prev);
+        assert!(view.has_next);
+    }
+
+    #[test]
+    fn paginate_returns_the_last_partial_page_with_no_next() {
+        let items = items(25);
+        let view = paginate(&items, 20);
+        assert_eq!(view.items, &items[20..25]);
+        assert!(view.has_prev);
+        assert!(!view.has_next);
+    }
+
+    #[test]
+    fn paginate_clamps_an_offset_past_the_end_to_the_last_page() {
+        let items = items(25);
+        let view = paginate(&items, 1_000);
+        assert_eq!(view.items, &items[20..25]);
+        assert_eq!(view.offset, 20);
+    }
+
+    #[test]
+    fn paginate_on_an_empty_list_is_a_single_empty_page() {
+        let items: Vec<String> = Vec::new();
+        let view = paginate(&items, 0);
+        assert!(view.items.is_empty());
+        assert!(!view.has_prev);
+        assert!(!view.has_next);
+    }
+
+    #[test]
+    fn a_token_round_trips_through_encode_and_decode() {
+        let token =
+            PageToken { command: PagedCommand::NextWords, target: "hello".to_string(), offset: 10, issuer_user_id: 42, issued_at_unix: 1_000 };
+        let encoded = token.encode().unwrap();
+        assert!(encoded.starts_with(CALLBACK_DATA_PREFIX));
+        assert_eq!(PageToken::decode(&encoded), Some(token));
+    }
+
+    #[test]
+    fn a_target_containing_a_colon_round_trips_intact() {
+        let token = PageToken { command: PagedCommand::NextWords, target: "a:b:c".to_string(), offset: 0, issuer_user_id: 1, issued_at_unix: 1 };
+        let encoded = token.encode().unwrap();
+        assert_eq!(PageToken::decode(&encoded).unwrap().target, "a:b:c");
+    }
+
+    #[test]
+    fn encode_refuses_a_target_that_would_overflow_the_callback_data_limit() {
+        let token = PageToken {
+            command: PagedCommand::NextWords,
+            target: "x".repeat(MAX_CALLBACK_DATA_LEN),
+            offset: 0,
+            issuer_user_id: 1,
+            issued_at_unix: 1,
+        };
+        assert_eq!(token.encode(), None);
+    }
+
+    #[test]
+    fn decode_rejects_data_without_the_page_prefix() {
+        assert_eq!(PageToken::decode("delete_confirm:1:42"), None);
+    }
+
+    #[test]
+    fn decode_rejects_an_unknown_command_code() {
+        assert_eq!(PageToken::decode("pg:z:0:1:1:"), None);
+    }
+
+    #[test]
+    fn decode_rejects_a_non_numeric_offset() {
+        assert_eq!(PageToken::decode("pg:s:not_a_number:1:1:"), None);
+    }
+
+    #[test]
+    fn a_token_expires_after_its_ttl() {
+        let token = PageToken { command: PagedCommand::Seeds, target: String::new(), offset: 0, issuer_user_id: 1, issued_at_unix: 1_000 };
+        assert!(!token.is_expired(1_000 + PAGE_TOKEN_TTL_SECS));
+        assert!(token.is_expired(1_000 + PAGE_TOKEN_TTL_SECS + 1));
+    }
+}
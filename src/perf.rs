@@ -0,0 +1,254 @@
+//! Lightweight self-instrumentation for `/msg` generation latency, so we can
+//! tell whether seeded generation scales linearly with chain size. Keeps a
+//! bounded reservoir sample per chat rather than every observation, so
+//! long-running chats don't grow this state without bound.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How many samples are kept per chat. Once a chat's reservoir fills,
+/// [`ReservoirSample::record`] replaces existing entries with decreasing
+/// probability, so the sample stays representative of the whole history.
+const RESERVOIR_CAPACITY: usize = 200;
+
+/// One `/msg` generation's timing, for the reservoir sample.
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationSample {
+    /// The number of distinct `(w1, w2)` contexts in the chain generated
+    /// from, i.e. its size independent of transition counts.
+    pub pair_key_count: usize,
+    pub seed_present: bool,
+    pub length_requirement_present: bool,
+    pub wall_time: Duration,
+}
+
+/// A fixed-capacity, uniformly-random sample of a stream of unknown length
+/// (Algorithm R / "reservoir sampling"). Every item seen so far has an equal
+/// probability of being among the retained samples.
+pub struct ReservoirSample<T> {
+    capacity: usize,
+    samples: Vec<T>,
+    seen: u64,
+}
+
+impl<T> ReservoirSample<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, samples: Vec::with_capacity(capacity), seen: 0 }
+    }
+
+    /// Offers `item` to the reservoir, keeping it if there's spare capacity
+    /// or if it wins the `1/seen` chance of replacing an existing sample.
+    pub fn record(&mut self, item: T, rng: &mut impl Rng) {
+        self.seen += 1;
+        if self.samples.len() < self.capacity {
+            self.samples.push(item);
+            return;
+        }
+        let slot = rng.random_range(0..self.seen);
+        if let Some(slot) = usize::try_from(slot).ok().filter(|&slot| slot < self.capacity) {
+            self.samples[slot] = item;
+        }
+    }
+
+    pub fn samples(&self) -> &[T] {
+        &self.samples
+    }
+}
+
+/// The size buckets `/perf` groups samples into, smallest first. A chain's
+/// `pair_key_count` falls into the first bucket whose bound it's under.
+const SIZE_BUCKETS: [(usize, &str); 4] = [(10, "<10"), (100, "<100"), (1_000, "<1,000"), (10_000, "<10,000")];
+const OVERFLOW_BUCKET_LABEL: &str = ">=10,000";
+
+/// Returns the bucket label `pair_key_count` falls into.
+fn bucket_label(pair_key_count: usize) -> &'static str {
+    for (bound, label) in SIZE_BUCKETS {
+        if pair_key_count < bound {
+            return label;
+        }
+    }
+    OVERFLOW_BUCKET_LABEL
+}
+
+/// One row of the `/perf` histogram: a size bucket, how many samples fell
+/// into it, and their average wall time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistogramBucket {
+    pub label: &'static str,
+    pub count: usize,
+    pub avg_wall_time: Duration,
+}
+
+/// Groups `samples` by [`bucket_label`], in bucket order, skipping empty
+/// buckets.
+pub fn histogram(samples: &[GenerationSample]) -> Vec<HistogramBucket> {
+    let mut totals: HashMap<&'static str, (usize, Duration)> = HashMap::new();
+    for sample in samples {
+        let entry = totals.entry(bucket_label(sample.pair_key_count)).or_insert((0, Duration::ZERO));
+        entry.0 += 1;
+        entry.1 += sample.wall_time;
+    }
+
+    SIZE_BUCKETS
+        .iter()
+        .map(|&(_, label)| label)
+        .chain(std::iter::once(OVERFLOW_BUCKET_LABEL))
+        .filter_map(|label| {
+            totals.get(label).map(|&(count, total)| HistogramBucket { label, count, avg_wall_time: total / count as u32 })
+        })
+        .collect()
+}
+
+/// Tracks generation latency samples per chat.
+pub struct PerfTracker {
+    per_chat: Mutex<HashMap<i64, ReservoirSample<GenerationSample>>>,
+}
+
+impl PerfTracker {
+    pub fn new() -> Self {
+        Self { per_chat: Mutex::new(HashMap::new()) }
+    }
+
+    /// Records one `/msg` generation's timing for `chat_id`.
+    pub fn record(&self, chat_id: i64, sample: GenerationSample) {
+        self.per_chat
+            .lock()
+            .unwrap()
+            .entry(chat_id)
+            .or_insert_with(|| ReservoirSample::new(RESERVOIR_CAPACITY))
+            .record(sample, &mut rand::rng());
+    }
+
+    /// Formats a `/perf` report for `chat_id`: a size-bucketed histogram of
+    /// recorded generation latencies, or a message if nothing's been
+    /// recorded yet.
+    pub fn report(&self, chat_id: i64) -> String {
+        let per_chat = self.per_chat.lock().unwrap();
+        let Some(reservoir) = per_chat.get(&chat_id) else {
+            return "No generation timings recorded yet for this chat.".to_string();
+        };
+
+        let buckets = histogram(reservoir.samples());
+        if buckets.is_empty() {
+            return "No generation timings recorded yet for this chat.".to_string();
+        }
+
+        let samples = reservoir.samples();
+        let seeded = samples.iter().filter(|s| s.seed_present).count();
+        let length_constrained = samples.iter().filter(|s| s.length_requirement_present).count();
+
+        let mut report = format!(
+            "Generation latency by chain size ({} sample(s), {seeded} seeded, {length_constrained} length-constrained):\n",
+            samples.len(),
+        );
+        for bucket in buckets {
+            report.push_str(&format!("- {}: {} sample(s), avg {:?}\n", bucket.label, bucket.count, bucket.avg_wall_time));
+        }
+        report
+    }
+}
+
+impl Default for PerfTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservoir_sample_keeps_everything_under_capacity() {
+        let mut reservoir = ReservoirSample::new(10);
+        let mut rng = rand::rng();
+        for i in 0..5 {
+            reservoir.record(i, &mut rng);
+        }
+        assert_eq!(reservoir.samples().len(), 5);
+    }
+
+    #[test]
+    fn reservoir_sample_never_exceeds_capacity() {
+        let mut reservoir = ReservoirSample::new(10);
+        let mut rng = rand::rng();
+        for i in 0..1000 {
+            reservoir.record(i, &mut rng);
+        }
+        assert_eq!(reservoir.samples().len(), 10);
+    }
+
+    #[test]
+    fn reservoir_sample_gives_every_item_a_chance_to_survive() {
+        // Statistical sanity check, not a strict proof: over many trials,
+        // items from both the early and late part of the stream should show
+        // up in the final reservoir at least once.
+        let mut early_survived = false;
+        let mut late_survived = false;
+        for trial in 0..200 {
+            let mut reservoir = ReservoirSample::new(5);
+            let mut rng = rand::rng();
+            for i in 0..100 {
+                reservoir.record(i, &mut rng);
+            }
+            if reservoir.samples().contains(&0) {
+                early_survived = true;
+            }
+            if reservoir.samples().contains(&99) {
+                late_survived = true;
+            }
+            if early_survived && late_survived {
+                break;
+            }
+            let _ = trial;
+        }
+        assert!(early_survived, "an early item should survive at least once across trials");
+        assert!(late_survived, "a late item should survive at least once across trials");
+    }
+
+    fn sample(pair_key_count: usize, millis: u64) -> GenerationSample {
+        GenerationSample {
+            pair_key_count,
+            seed_present: false,
+            length_requirement_present: false,
+            wall_time: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn bucket_label_assigns_expected_buckets() {
+        assert_eq!(bucket_label(0), "<10");
+        assert_eq!(bucket_label(9), "<10");
+        assert_eq!(bucket_label(10), "<100");
+        assert_eq!(bucket_label(999), "<1,000");
+        assert_eq!(bucket_label(1_000), "<10,000");
+        assert_eq!(bucket_label(10_000), ">=10,000");
+    }
+
+    #[test]
+    fn histogram_groups_and_averages_by_bucket_in_order() {
+        let samples = vec![sample(5, 10), sample(5, 30), sample(50, 100), sample(20_000, 5)];
+        let buckets = histogram(&samples);
+
+        assert_eq!(
+            buckets,
+            vec![
+                HistogramBucket { label: "<10", count: 2, avg_wall_time: Duration::from_millis(20) },
+                HistogramBucket { label: "<100", count: 1, avg_wall_time: Duration::from_millis(100) },
+                HistogramBucket { label: ">=10,000", count: 1, avg_wall_time: Duration::from_millis(5) },
+            ]
+        );
+    }
+
+    #[test]
+    fn perf_tracker_reports_per_chat_and_is_isolated_between_chats() {
+        let tracker = PerfTracker::new();
+        tracker.record(1, sample(5, 10));
+
+        assert!(tracker.report(1).contains("<10"));
+        assert_eq!(tracker.report(2), "No generation timings recorded yet for this chat.");
+    }
+}
@@ -0,0 +1,118 @@
+//! Computes the `getUpdates` subscription this bot should request, based on
+//! which optional update kinds are enabled in [`crate::config::Config`].
+//!
+//! `Update::Message` is always requested - it's the whole reason this bot
+//! exists - the rest are opt-out, so a deployment that doesn't want, say,
+//! inline query autocomplete also stops paying Telegram's long-poll
+//! bandwidth for it, not just its handler branch in
+//! [`crate::markov_telegram_bot::handler`]. `teloxide`'s [`Dispatcher`] would
+//! otherwise infer this list from the handler tree itself (see
+//! [`UpdateListener::hint_allowed_updates`]), which is enough for a fixed
+//! handler tree, but not for toggling a kind off at the source.
+//!
+//! [`Dispatcher`]: teloxide::dispatching::Dispatcher
+//! [`UpdateListener::hint_allowed_updates`]: teloxide::update_listeners::UpdateListener::hint_allowed_updates
+
+use teloxide::types::AllowedUpdate;
+
+/// Which optional inbound update kinds this deployment wants delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollFeatures {
+    /// Backs `/msg`'s seed autocomplete (`handle_inline_query`).
+    pub inline_queries: bool,
+    /// Backs the quarantine and onboarding buttons (`handle_callback_query`).
+    pub callback_queries: bool,
+    /// Backs onboarding a newly joined chat (`handle_bot_membership_change`).
+    pub chat_membership_changes: bool,
+    /// Backs learning from, and serving `/msg` in, channels the bot is an
+    /// admin of (`learn_message`/`handle_command`, routed via
+    /// `Update::filter_channel_post`). Requests `edited_channel_post` too,
+    /// even though nothing handles it yet, for parity with `channel_post` -
+    /// same as how `Message` doesn't imply `edited_message` is handled
+    /// either.
+    pub channel_posts: bool,
+}
+
+/// Computes the `allowed_updates` list to request from `getUpdates` for
+/// `features`.
+pub fn allowed_updates(features: PollFeatures) -> Vec<AllowedUpdate> {
+    let mut updates = vec![AllowedUpdate::Message];
+    if features.inline_queries {
+        updates.push(AllowedUpdate::InlineQuery);
+    }
+    if features.callback_queries {
+        updates.push(AllowedUpdate::CallbackQuery);
+    }
+    if features.chat_membership_changes {
+        updates.push(AllowedUpdate::MyChatMember);
+    }
+    if features.channel_posts {
+        updates.push(AllowedUpdate::ChannelPost);
+        updates.push(AllowedUpdate::EditedChannelPost);
+    }
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_DISABLED: PollFeatures =
+        PollFeatures { inline_queries: false, callback_queries: false, chat_membership_changes: false, channel_posts: false };
+
+    #[test]
+    fn message_is_always_requested() {
+        assert_eq!(allowed_updates(ALL_DISABLED), vec![AllowedUpdate::Message]);
+    }
+
+    #[test]
+    fn enabling_inline_queries_requests_them() {
+        let updates = allowed_updates(PollFeatures { inline_queries: true, ..ALL_DISABLED });
+        assert!(updates.contains(&AllowedUpdate::InlineQuery));
+        assert!(!updates.contains(&AllowedUpdate::CallbackQuery));
+        assert!(!updates.contains(&AllowedUpdate::MyChatMember));
+    }
+
+    #[test]
+    fn enabling_callback_queries_requests_them() {
+        let updates = allowed_updates(PollFeatures { callback_queries: true, ..ALL_DISABLED });
+        assert!(updates.contains(&AllowedUpdate::CallbackQuery));
+        assert!(!updates.contains(&AllowedUpdate::InlineQuery));
+    }
+
+    #[test]
+    fn enabling_chat_membership_changes_requests_them() {
+        let updates = allowed_updates(PollFeatures { chat_membership_changes: true, ..ALL_DISABLED });
+        assert!(updates.contains(&AllowedUpdate::MyChatMember));
+        assert!(!updates.contains(&AllowedUpdate::InlineQuery));
+    }
+
+    #[test]
+    fn enabling_channel_posts_requests_them_and_their_edits() {
+        let updates = allowed_updates(PollFeatures { channel_posts: true, ..ALL_DISABLED });
+        assert!(updates.contains(&AllowedUpdate::ChannelPost));
+        assert!(updates.contains(&AllowedUpdate::EditedChannelPost));
+        assert!(!updates.contains(&AllowedUpdate::InlineQuery));
+    }
+
+    #[test]
+    fn enabling_every_feature_requests_every_update_kind() {
+        let updates = allowed_updates(PollFeatures {
+            inline_queries: true,
+            callback_queries: true,
+            chat_membership_changes: true,
+            channel_posts: true,
+        });
+        assert_eq!(
+            updates,
+            vec![
+                AllowedUpdate::Message,
+                AllowedUpdate::InlineQuery,
+                AllowedUpdate::CallbackQuery,
+                AllowedUpdate::MyChatMember,
+                AllowedUpdate::ChannelPost,
+                AllowedUpdate::EditedChannelPost,
+            ]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Startup warm-up: preloads the busiest chats' data before the polling loop
+//! starts accepting updates, so the first `/msg` in a big chat after a
+//! restart doesn't pay a full cold read of its (potentially multi-megabyte)
+//! chain data.
+//!
+//! There is no read-through cache wrapping [`Storage`] yet - command
+//! handlers still call [`Storage::read_chat_data`] directly on every
+//! request. [`ChatDataCache`] here only holds what [`preload_top_chats`]
+//! fetches at startup; wiring command handlers to consult it first (so a
+//! warm chat stays warm after its first read too) is a larger change to the
+//! read path and is left for a future request.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+
+use crate::markov_telegram_bot::{ChainLookup, ChatData, Storage, ALL_KEY};
+
+/// Holds chat data preloaded by [`preload_top_chats`], keyed by chat ID.
+#[derive(Default)]
+pub struct ChatDataCache {
+    entries: Mutex<HashMap<i64, ChatData>>,
+}
+
+impl ChatDataCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Not compiled into non-test builds: nothing outside tests reads back
+    /// from the cache yet, since no command handler consults it (see the
+    /// module doc comment).
+    #[cfg(test)]
+    pub fn get(&self, chat_id: i64) -> Option<ChatData> {
+        self.entries.lock().unwrap().get(&chat_id).cloned()
+    }
+
+    pub fn insert(&self, chat_id: i64, data: ChatData) {
+        self.entries.lock().unwrap().insert(chat_id, data);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// What one preload run did, for the startup log line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreloadReport {
+    /// The chats selected for preloading, most active first.
+    pub selected: Vec<i64>,
+    /// The subset of `selected` that actually finished loading before the
+    /// time budget ran out.
+    pub preloaded: Vec<i64>,
+    /// Whether the time budget was exhausted before every selected chat
+    /// finished loading.
+    pub timed_out: bool,
+}
+
+/// Ranks every known chat by its combined chain's transition count (a proxy
+/// for "busy", since nothing tracks activity across a restart - see
+/// [`crate::stats_export::ActivityCounters`], which resets on every export
+/// and starts empty on a fresh process) and returns the IDs of the top `top_k`.
+/// Uses [`Storage::read_user_chain`] rather than [`Storage::read_chat_data`]
+/// for the ranking pass itself, so ranking a chat doesn't pay the same full
+/// document read that preloading it is trying to avoid paying twice.
+pub async fn select_top_active_chats(storage: &dyn Storage, top_k: usize) -> Vec<i64> {
+    let chat_ids = match storage.list_chat_ids().await {
+        Ok(ids) => ids,
+        Err(err) => {
+            log::error!("failed to list chats for startup preload: {err}");
+            return Vec::new();
+        }
+    };
+
+    let mut ranked = Vec::with_capacity(chat_ids.len());
+    for chat_id in chat_ids {
+        let size = match storage.read_user_chain(chat_id, ALL_KEY).await {
+            Ok(ChainLookup::Found(chain)) => chain.transition_count(),
+            _ => 0,
+        };
+        ranked.push((chat_id, size));
+    }
+    ranked.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked.into_iter().take(top_k).map(|(chat_id, _)| chat_id).collect()
+}
+
+/// Preloads the top `top_k` most active chats' data into `cache`, reading up
+/// to `concurrency` chats at once, and gives up waiting for stragglers once
+/// `time_budget` has elapsed so a slow or oversized chat can't delay startup
+/// indefinitely - whatever didn't finish in time is simply read cold on its
+/// first real request, same as before this existed.
+pub async fn preload_top_chats(storage: &dyn Storage, cache: &ChatDataCache, top_k: usize, concurrency: usize, time_budget: Duration) -> PreloadReport {
+    let selected = select_top_active_chats(storage, top_k).await;
+
+    let preloaded = Mutex::new(Vec::new());
+    let fetches = stream::iter(selected.clone()).map(|chat_id| {
+        let preloaded = &preloaded;
+        async move {
+            match storage.read_chat_data(chat_id).await {
+                Ok(Some(data)) => {
+                    cache.insert(chat_id, data);
+                    preloaded.lock().unwrap().push(chat_id);
+                }
+                Ok(None) => {}
+                Err(err) => log::error!("failed to preload chat {chat_id}: {err}"),
+            }
+        }
+    });
+    let run_all = fetches.buffer_unordered(concurrency.max(1)).collect::<Vec<()>>();
+
+    let timed_out = tokio::time::timeout(time_budget, run_all).await.is_err();
+    PreloadReport { selected, preloaded: preloaded.into_inner().unwrap(), timed_out }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markov_telegram_bot::{learn_into, InMemoryStorage};
+
+    #[tokio::test]
+    async fn select_top_active_chats_ranks_by_combined_transition_count() {
+        let storage = InMemoryStorage::new();
+        learn_into(&storage, 1, None, 1, "a b c").await.unwrap();
+        learn_into(&storage, 2, None, 1, "a b c d e f g h").await.unwrap();
+        learn_into(&storage, 3, None, 1, "a b").await.unwrap();
+
+        let selected = select_top_active_chats(&storage, 2).await;
+        assert_eq!(selected, vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn select_top_active_chats_is_empty_when_nothing_is_stored() {
+        let storage = InMemoryStorage::new();
+        assert_eq!(select_top_active_chats(&storage, 5).await, Vec::<i64>::new());
+    }
+
+    #[tokio::test]
+    async fn preload_top_chats_populates_the_cache_for_the_selected_chats() {
+        let storage = InMemoryStorage::new();
+        learn_into(&storage, 1, None, 1, "busiest chat here").await.unwrap();
+        learn_into(&storage, 1, None, 1, "busiest chat here").await.unwrap();
+        learn_into(&storage, 2, None, 1, "quieter chat").await.unwrap();
+
+        let cache = ChatDataCache::new();
+        let report = preload_top_chats(&storage, &cache, 1, 4, Duration::from_secs(5)).await;
+
+        assert_eq!(report.selected, vec![1]);
+        assert_eq!(report.preloaded, vec![1]);
+        assert!(!report.timed_out);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn preload_top_chats_respects_an_exhausted_time_budget() {
+        let storage = InMemoryStorage::new();
+        for chat_id in 1..=5 {
+            learn_into(&storage, chat_id, None, 1, "hello world").await.unwrap();
+        }
+        storage.set_read_chat_data_delay(Duration::from_secs(60));
+
+        let cache = ChatDataCache::new();
+        let report = preload_top_chats(&storage, &cache, 5, 1, Duration::from_secs(1)).await;
+
+        assert!(report.timed_out);
+        assert_eq!(report.selected.len(), 5);
+        assert!(report.preloaded.is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+//! A single presentation layer sitting between command handlers and the
+//! chat: handlers build a [`CommandOutcome`] describing what happened, and
+//! [`render`] turns it into either prose (the bot's original voice) or a
+//! compact JSON object for bridge bots that need machine-readable replies
+//! (see `/jsonmode` and `/msg json ...`).
+
+use serde::Serialize;
+
+/// Whether a [`CommandOutcome`] represents success or failure, surfaced as
+/// JSON mode's `type` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeKind {
+    Ok,
+    Error,
+}
+
+/// A command handler's result, decoupled from how it's rendered to the
+/// chat. `text` is always the human-readable prose reply; `source`/`seed`
+/// and `error_code` are populated only when relevant to the specific
+/// command, and are omitted from JSON output when absent.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommandOutcome {
+    #[serde(rename = "type")]
+    pub kind: OutcomeKind,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+impl CommandOutcome {
+    /// A successful reply with no structured metadata beyond its text.
+    pub fn ok(text: impl Into<String>) -> Self {
+        Self { kind: OutcomeKind::Ok, text: text.into(), source: None, seed: None, error_code: None }
+    }
+
+    /// A failed reply, tagged with a short machine-readable `error_code`
+    /// (e.g. `"no_data"`, `"unknown_seed"`) alongside its prose `text`.
+    pub fn error(error_code: &str, text: impl Into<String>) -> Self {
+        Self { kind: OutcomeKind::Error, text: text.into(), source: None, seed: None, error_code: Some(error_code.to_string()) }
+    }
+
+    /// Attaches the `/msg` source (`"all"` or a user ID) this outcome was
+    /// generated for.
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Attaches the seed word this outcome was generated from.
+    pub fn with_seed(mut self, seed: impl Into<String>) -> Self {
+        self.seed = Some(seed.into());
+        self
+    }
+
+    /// Shorthand for `self.text.contains(pat)`, so test assertions can check
+    /// reply content without reaching into the `text` field.
+    #[cfg(test)]
+    pub fn contains(&self, pat: &str) -> bool {
+        self.text.contains(pat)
+    }
+
+    /// Shorthand for `self.text.starts_with(pat)`.
+    #[cfg(test)]
+    pub fn starts_with(&self, pat: &str) -> bool {
+        self.text.starts_with(pat)
+    }
+}
+
+impl PartialEq<str> for CommandOutcome {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl PartialEq<&str> for CommandOutcome {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+/// Renders `outcome` as prose (the bot's original plain-text replies) or, in
+/// JSON mode, as a compact JSON object inside a Markdown code block so
+/// bridge bots can parse it out of the message text.
+pub fn render(outcome: &CommandOutcome, json_mode: bool) -> String {
+    if !json_mode {
+        return outcome.text.clone();
+    }
+    match serde_json::to_string(outcome) {
+        Ok(json) => format!("```json\n{json}\n```"),
+        Err(err) => {
+            log::error!("failed to serialize command outcome as JSON: {err}");
+            outcome.text.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_in_prose_mode_returns_bare_text() {
+        let outcome = CommandOutcome::ok("hello there");
+        assert_eq!(render(&outcome, false), "hello there");
+    }
+
+    #[test]
+    fn render_in_json_mode_wraps_a_compact_json_object_in_a_code_block() {
+        let outcome = CommandOutcome::ok("hello there").with_source("all").with_seed("hello");
+        let rendered = render(&outcome, true);
+        assert!(rendered.starts_with("```json\n"));
+        assert!(rendered.ends_with("\n```"));
+        assert!(rendered.contains(r#""type":"ok""#));
+        assert!(rendered.contains(r#""text":"hello there""#));
+        assert!(rendered.contains(r#""source":"all""#));
+        assert!(rendered.contains(r#""seed":"hello""#));
+    }
+
+    #[test]
+    fn render_in_json_mode_omits_absent_optional_fields() {
+        let outcome = CommandOutcome::ok("hi");
+        let rendered = render(&outcome, true);
+        assert!(!rendered.contains("source"));
+        assert!(!rendered.contains("seed"));
+        assert!(!rendered.contains("error_code"));
+    }
+
+    #[test]
+    fn error_outcome_carries_its_error_code_in_json_mode() {
+        let outcome = CommandOutcome::error("no_data", "I haven't learned anything yet.");
+        let rendered = render(&outcome, true);
+        assert!(rendered.contains(r#""type":"error""#));
+        assert!(rendered.contains(r#""error_code":"no_data""#));
+    }
+}
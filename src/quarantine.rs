@@ -0,0 +1,232 @@
+//! Burst / chain-poisoning detection: tracks a short-window per-user message
+//! rate and repeated-content ratio, and diverts a user's messages into a
+//! bounded per-chat quarantine buffer (instead of learning from them) once
+//! they look like a deliberate attempt to flood a phrase into the chain.
+//!
+//! Like [`crate::stats_export::ActivityCounters`] and the bot's other
+//! in-process trackers, detector and buffer state live only for the process
+//! lifetime; losing them on restart just resets the window, which is
+//! acceptable for a spam heuristic.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// How far back the detector looks when judging a user's recent activity.
+const WINDOW_SECS: i64 = 60;
+/// A user posting at least this many messages within [`WINDOW_SECS`] is
+/// considered bursting.
+const RATE_THRESHOLD: usize = 20;
+/// Of the messages in the window, at least this fraction must share the same
+/// content for a burst to be treated as poisoning rather than fast ordinary
+/// chatter.
+const REPEATED_CONTENT_RATIO: f64 = 0.6;
+/// How many quarantined messages are kept per chat before the oldest are
+/// dropped, bounding memory for a chat under sustained attack.
+const QUARANTINE_CAPACITY: usize = 500;
+
+/// The outcome of running one message through the [`BurstDetector`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BurstVerdict {
+    /// Not part of a detected burst; learn from it as usual.
+    Clear,
+    /// Part of a detected burst; divert it to the quarantine buffer instead
+    /// of learning from it. `first_detection` is set only on the message
+    /// that tipped the user into quarantine, so callers can notify the chat
+    /// once per burst rather than once per message.
+    Quarantined { first_detection: bool },
+}
+
+struct UserWindow {
+    /// `(timestamp, content hash)` pairs within the last [`WINDOW_SECS`].
+    events: VecDeque<(i64, u64)>,
+    /// Set while this user's messages are being diverted, until this unix
+    /// timestamp.
+    quarantined_until: Option<i64>,
+}
+
+/// Detects per-user message bursts with a high ratio of repeated content,
+/// e.g. the same phrase pasted dozens of times to force it into `/msg all`.
+#[derive(Default)]
+pub struct BurstDetector {
+    windows: Mutex<HashMap<(i64, i64), UserWindow>>,
+}
+
+impl BurstDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one message from `user_id` in `chat_id` at `now_unix` and
+    /// returns whether it should be learned from or quarantined.
+    pub fn observe(&self, chat_id: i64, user_id: i64, now_unix: i64, text: &str) -> BurstVerdict {
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry((chat_id, user_id)).or_insert_with(|| UserWindow {
+            events: VecDeque::new(),
+            quarantined_until: None,
+        });
+
+        window.events.push_back((now_unix, hash_text(text)));
+        while window.events.front().is_some_and(|&(timestamp, _)| now_unix - timestamp > WINDOW_SECS) {
+            window.events.pop_front();
+        }
+
+        if let Some(until) = window.quarantined_until {
+            if now_unix < until {
+                return BurstVerdict::Quarantined { first_detection: false };
+            }
+            window.quarantined_until = None;
+        }
+
+        if window.events.len() >= RATE_THRESHOLD && most_common_ratio(&window.events) >= REPEATED_CONTENT_RATIO {
+            window.quarantined_until = Some(now_unix + WINDOW_SECS);
+            return BurstVerdict::Quarantined { first_detection: true };
+        }
+
+        BurstVerdict::Clear
+    }
+}
+
+/// Returns the fraction of `events` sharing the most common content hash.
+fn most_common_ratio(events: &VecDeque<(i64, u64)>) -> f64 {
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for &(_, hash) in events {
+        *counts.entry(hash).or_insert(0) += 1;
+    }
+    let most_common = counts.values().copied().max().unwrap_or(0);
+    most_common as f64 / events.len() as f64
+}
+
+fn hash_text(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// One message diverted from learning, awaiting an admin's approve/discard
+/// decision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedMessage {
+    pub user_id: i64,
+    pub text: String,
+}
+
+/// Holds quarantined messages per chat, bounded so a sustained attack can't
+/// grow this without bound.
+#[derive(Default)]
+pub struct QuarantineBuffer {
+    per_chat: Mutex<HashMap<i64, VecDeque<QuarantinedMessage>>>,
+}
+
+impl QuarantineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `message` to `chat_id`'s buffer, dropping the oldest entry if
+    /// it's at capacity.
+    pub fn push(&self, chat_id: i64, message: QuarantinedMessage) {
+        let mut per_chat = self.per_chat.lock().unwrap();
+        let queue = per_chat.entry(chat_id).or_default();
+        queue.push_back(message);
+        while queue.len() > QUARANTINE_CAPACITY {
+            queue.pop_front();
+        }
+    }
+
+    /// Removes and returns every quarantined message for `chat_id`, for an
+    /// admin's approve (learn them) or discard (drop them) decision.
+    pub fn take_all(&self, chat_id: i64) -> Vec<QuarantinedMessage> {
+        self.per_chat.lock().unwrap().remove(&chat_id).map(Vec::from).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_chatter_is_never_quarantined() {
+        let detector = BurstDetector::new();
+        for i in 0..100 {
+            let verdict = detector.observe(1, 42, i * 10, &format!("distinct message {i}"));
+            assert_eq!(verdict, BurstVerdict::Clear);
+        }
+    }
+
+    #[test]
+    fn a_fast_repeated_burst_is_quarantined() {
+        let detector = BurstDetector::new();
+        let mut first_detection_seen = false;
+        for i in 0..RATE_THRESHOLD {
+            let verdict = detector.observe(1, 42, i as i64, "spam phrase");
+            if i + 1 < RATE_THRESHOLD {
+                assert_eq!(verdict, BurstVerdict::Clear, "burst shouldn't trip before the rate threshold");
+            } else {
+                assert_eq!(verdict, BurstVerdict::Quarantined { first_detection: true });
+                first_detection_seen = true;
+            }
+        }
+        assert!(first_detection_seen);
+    }
+
+    #[test]
+    fn a_high_rate_of_distinct_content_is_not_quarantined() {
+        let detector = BurstDetector::new();
+        for i in 0..RATE_THRESHOLD * 2 {
+            let verdict = detector.observe(1, 42, i as i64, &format!("unique message {i}"));
+            assert_eq!(verdict, BurstVerdict::Clear);
+        }
+    }
+
+    #[test]
+    fn quarantine_persists_for_the_rest_of_the_window_then_clears() {
+        let detector = BurstDetector::new();
+        for i in 0..RATE_THRESHOLD {
+            detector.observe(1, 42, i as i64, "spam phrase");
+        }
+
+        // Still within the quarantine window: further messages stay quarantined,
+        // but aren't treated as a fresh first detection.
+        let verdict = detector.observe(1, 42, RATE_THRESHOLD as i64, "anything at all");
+        assert_eq!(verdict, BurstVerdict::Quarantined { first_detection: false });
+
+        // Well past the window: quarantine lifts.
+        let verdict = detector.observe(1, 42, RATE_THRESHOLD as i64 + WINDOW_SECS + 1, "back to normal");
+        assert_eq!(verdict, BurstVerdict::Clear);
+    }
+
+    #[test]
+    fn detectors_are_isolated_per_chat_and_user() {
+        let detector = BurstDetector::new();
+        for i in 0..RATE_THRESHOLD {
+            detector.observe(1, 42, i as i64, "spam phrase");
+        }
+        assert_eq!(detector.observe(1, 43, 0, "spam phrase"), BurstVerdict::Clear);
+        assert_eq!(detector.observe(2, 42, 0, "spam phrase"), BurstVerdict::Clear);
+    }
+
+    #[test]
+    fn quarantine_buffer_bounds_capacity_per_chat() {
+        let buffer = QuarantineBuffer::new();
+        for i in 0..QUARANTINE_CAPACITY + 10 {
+            buffer.push(1, QuarantinedMessage { user_id: 42, text: format!("message {i}") });
+        }
+        let messages = buffer.take_all(1);
+        assert_eq!(messages.len(), QUARANTINE_CAPACITY);
+        assert_eq!(messages[0].text, "message 10");
+    }
+
+    #[test]
+    fn quarantine_buffer_take_all_drains_and_is_per_chat() {
+        let buffer = QuarantineBuffer::new();
+        buffer.push(1, QuarantinedMessage { user_id: 42, text: "a".to_string() });
+        buffer.push(2, QuarantinedMessage { user_id: 43, text: "b".to_string() });
+
+        let chat1 = buffer.take_all(1);
+        assert_eq!(chat1, vec![QuarantinedMessage { user_id: 42, text: "a".to_string() }]);
+        assert!(buffer.take_all(1).is_empty());
+        assert_eq!(buffer.take_all(2).len(), 1);
+    }
+}
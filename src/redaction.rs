@@ -0,0 +1,220 @@
+//! Stripping sensitive substrings out of a message before it's tokenized and
+//! learned (see [`crate::markov_telegram_bot::learn_into_with_order`]), so a
+//! chain never memorizes, say, a phone number or an invite link just because
+//! someone pasted one into the chat.
+//!
+//! A chat opts in per-pattern via `/redact`: any number of custom regexes,
+//! plus two built-in patterns (phone-number-like digit runs, `t.me` invite
+//! links) that can be toggled independently of the custom list. A match is
+//! deleted outright rather than replaced with a placeholder token, so the
+//! words on either side of it are learned as adjacent - the same as if the
+//! sensitive text had never been typed.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// How many custom patterns a chat may have configured at once, so `/redact
+/// list` and the per-message redaction pass both stay cheap regardless of
+/// how enthusiastically admins add patterns.
+pub const MAX_PATTERNS: usize = 20;
+
+/// How long a single custom pattern's source text may be, checked before
+/// it's ever compiled.
+pub const MAX_PATTERN_LENGTH: usize = 200;
+
+/// Above this length, a message is learned unredacted rather than run
+/// through every configured pattern. The `regex` crate's matching is
+/// linear-time in the input (no catastrophic backtracking, unlike a
+/// backtracking engine), so this isn't a defense against a slow pattern -
+/// it's a flat backstop on total match-time budget per message, and in
+/// practice never trips, since Telegram already caps text messages at 4096
+/// characters.
+const MAX_REDACTION_INPUT_LEN: usize = 4096;
+
+/// A chat's redaction configuration, set via `/redact`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactionSettings {
+    /// Custom regexes added via `/redact add <pattern>`, checked in
+    /// insertion order.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Whether the built-in phone-number-like pattern is applied, set via
+    /// `/redact phones on|off`.
+    #[serde(default)]
+    pub redact_phone_numbers: bool,
+    /// Whether the built-in `t.me` invite link pattern is applied, set via
+    /// `/redact links on|off`.
+    #[serde(default)]
+    pub redact_invite_links: bool,
+}
+
+impl RedactionSettings {
+    /// Whether this chat has any redaction configured at all, custom or
+    /// built-in - lets [`redact`] skip straight past a chat that hasn't
+    /// opted into anything.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && !self.redact_phone_numbers && !self.redact_invite_links
+    }
+}
+
+/// Built-in pattern behind `/redact phones`: runs of 7 or more digits,
+/// optionally separated by spaces, dashes, dots, or parentheses, with an
+/// optional leading `+`. Deliberately loose - it's meant to catch
+/// obviously-a-phone-number sequences, not validate real numbering plans.
+const PHONE_NUMBER_PATTERN: &str = r"\+?[0-9][0-9()\-. ]{6,}[0-9]";
+
+/// Built-in pattern behind `/redact links`: `t.me` invite links, with or
+/// without a scheme, covering both public `t.me/name` links and private
+/// `t.me/+hash`/`t.me/joinchat/hash` invite links.
+const INVITE_LINK_PATTERN: &str = r"(?i)(https?://)?t\.me/(joinchat/)?[+A-Za-z0-9_-]+";
+
+/// Compiles `pattern`, rejecting it outright if it's too long to be worth
+/// caching (see [`MAX_PATTERN_LENGTH`]) or doesn't parse as a regex.
+/// [`crate::markov_telegram_bot::do_redact_command`] calls this at
+/// `/redact add` time so a bad pattern is rejected immediately, rather than
+/// silently never matching anything once learning starts.
+pub fn compile_pattern(pattern: &str) -> Result<Regex, String> {
+    if pattern.len() > MAX_PATTERN_LENGTH {
+        return Err(format!("Pattern is too long (max {MAX_PATTERN_LENGTH} characters)."));
+    }
+    Regex::new(pattern).map_err(|err| format!("\"{pattern}\" isn't a valid regex: {err}"))
+}
+
+/// Process-wide cache of compiled custom patterns, keyed by their source
+/// text, so a pattern shared across chats (or reused across messages in the
+/// same chat) is compiled once rather than on every message it's applied to.
+/// Entries are never evicted: with [`MAX_PATTERNS`] enforced per chat and
+/// patterns being short, plain strings, the total distinct pattern count in
+/// practice stays small relative to a long-running process's lifetime.
+fn pattern_cache() -> &'static Mutex<HashMap<String, Regex>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Process-wide cache of the two built-in patterns, compiled on first use.
+fn builtin_pattern(pattern: &'static str) -> &'static Mutex<HashMap<String, Regex>> {
+    // Reuses the same cache as custom patterns - the built-ins are just two
+    // more entries in it, keyed by their own source text like everything
+    // else.
+    let cache = pattern_cache();
+    cache.lock().unwrap().entry(pattern.to_string()).or_insert_with(|| Regex::new(pattern).expect("built-in pattern is valid"));
+    cache
+}
+
+/// Runs `pattern` (a previously-validated pattern already known to compile)
+/// against `text`, fetching it from [`pattern_cache`] and compiling it in
+/// only on a cache miss.
+fn apply_cached(cache: &Mutex<HashMap<String, Regex>>, pattern: &str, text: &str) -> String {
+    let mut guard = cache.lock().unwrap();
+    let regex = guard.entry(pattern.to_string()).or_insert_with(|| {
+        // A stored pattern was already validated by `compile_pattern` at
+        // `/redact add` time; if it somehow doesn't compile now (e.g. a
+        // future regex version tightens what it accepts), skip it rather
+        // than panicking on someone else's already-learned text.
+        Regex::new(pattern).unwrap_or_else(|_| Regex::new(r"$^").unwrap())
+    });
+    regex.replace_all(text, " ").into_owned()
+}
+
+/// Removes every substring of `text` matching one of `settings`'s patterns,
+/// closing the gap left behind (matches become a single space, then
+/// [`crate::tokenizer::tokenize`]'s whitespace splitting collapses runs of
+/// them) so the surrounding words are learned as adjacent. Returns `text`
+/// unchanged if `settings` is empty or `text` is over
+/// [`MAX_REDACTION_INPUT_LEN`].
+pub fn redact(settings: &RedactionSettings, text: &str) -> String {
+    if settings.is_empty() || text.len() > MAX_REDACTION_INPUT_LEN {
+        return text.to_string();
+    }
+
+    let mut redacted = text.to_string();
+    for pattern in &settings.patterns {
+        redacted = apply_cached(pattern_cache(), pattern, &redacted);
+    }
+    if settings.redact_phone_numbers {
+        redacted = apply_cached(builtin_pattern(PHONE_NUMBER_PATTERN), PHONE_NUMBER_PATTERN, &redacted);
+    }
+    if settings.redact_invite_links {
+        redacted = apply_cached(builtin_pattern(INVITE_LINK_PATTERN), INVITE_LINK_PATTERN, &redacted);
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_settings_leave_text_untouched() {
+        assert_eq!(redact(&RedactionSettings::default(), "call me at 555-123-4567"), "call me at 555-123-4567");
+    }
+
+    #[test]
+    fn custom_pattern_removes_matches_and_closes_the_gap() {
+        let settings = RedactionSettings { patterns: vec!["secret".to_string()], ..Default::default() };
+        assert_eq!(redact(&settings, "the secret word is secret today"), "the   word is   today");
+    }
+
+    #[test]
+    fn overlapping_custom_patterns_apply_in_order() {
+        let settings =
+            RedactionSettings { patterns: vec!["foobar".to_string(), "barbaz".to_string()], ..Default::default() };
+        // "foobar" and "barbaz" overlap on "bar" in the source text. The
+        // first pattern's match consumes it, so the second pattern - which
+        // needed "bar" as its own prefix - no longer finds anything to
+        // match once its turn comes.
+        assert_eq!(redact(&settings, "foobarbaz"), " baz");
+    }
+
+    #[test]
+    fn a_fully_redacted_message_reduces_to_whitespace() {
+        let settings = RedactionSettings { patterns: vec![r"\w+".to_string()], ..Default::default() };
+        assert_eq!(redact(&settings, "everything here matches").trim(), "");
+    }
+
+    #[test]
+    fn phone_numbers_are_redacted_when_enabled() {
+        let settings = RedactionSettings { redact_phone_numbers: true, ..Default::default() };
+        assert_eq!(redact(&settings, "reach me at +1 555-123-4567 anytime"), "reach me at   anytime");
+        assert_eq!(redact(&settings, "reach me at +1 555-123-4567 anytime"), redact(&settings, "reach me at +1 555-123-4567 anytime"));
+    }
+
+    #[test]
+    fn phone_numbers_are_left_alone_when_disabled() {
+        let settings = RedactionSettings::default();
+        assert_eq!(redact(&settings, "reach me at 555-123-4567"), "reach me at 555-123-4567");
+    }
+
+    #[test]
+    fn invite_links_are_redacted_when_enabled() {
+        let settings = RedactionSettings { redact_invite_links: true, ..Default::default() };
+        assert_eq!(redact(&settings, "join us at https://t.me/joinchat/AbC123 for more"), "join us at   for more");
+        assert_eq!(redact(&settings, "or just t.me/somechannel"), "or just  ");
+    }
+
+    #[test]
+    fn compile_pattern_rejects_invalid_regex() {
+        assert!(compile_pattern("(unclosed").is_err());
+    }
+
+    #[test]
+    fn compile_pattern_rejects_overly_long_patterns() {
+        let pattern = "a".repeat(MAX_PATTERN_LENGTH + 1);
+        assert!(compile_pattern(&pattern).is_err());
+    }
+
+    #[test]
+    fn compile_pattern_accepts_a_valid_regex() {
+        assert!(compile_pattern(r"\d{3}-\d{4}").is_ok());
+    }
+
+    #[test]
+    fn oversized_input_is_learned_unredacted() {
+        let settings = RedactionSettings { redact_phone_numbers: true, ..Default::default() };
+        let huge = format!("{} 555-123-4567", "word ".repeat(MAX_REDACTION_INPUT_LEN));
+        assert_eq!(redact(&settings, &huge), huge);
+    }
+}
@@ -0,0 +1,326 @@
+//! A `replay` CLI subcommand: replays a JSONL fixture of scripted chat
+//! events against [`InMemoryStorage`], so reviewing changes to parsing,
+//! learning, and generation doesn't require a live Telegram connection or a
+//! MongoDB instance.
+//!
+//! This replays the storage-driven core of the message pipeline -
+//! [`is_message_learning_allowed`]/[`learn_with_journal`] for plain text, and
+//! [`parse_msg_command_params`]/[`do_msg_command`] for `/msg` - not the
+//! literal `learn_message`/`handle_command` endpoints, since those take a
+//! concrete `teloxide::Bot` this bot has no mock for (the same limitation
+//! `handle_quarantine_callback`'s doc comment notes elsewhere: there's no
+//! Bot-mocking infrastructure in this codebase). Anything only a real `Bot`
+//! would do - sending replies, answering callbacks, burst-quarantine
+//! notifications - is out of scope here.
+
+use std::collections::HashMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::markov_chain::EntropyReport;
+use crate::markov_telegram_bot::{
+    do_msg_command, is_message_learning_allowed, learn_with_journal, parse_msg_command_params, InMemoryStorage, Storage,
+    UserInfo, ALL_KEY,
+};
+use crate::perf::PerfTracker;
+
+/// One scripted update from a fixture, one JSON object per JSONL line.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayEvent {
+    pub chat_id: i64,
+    pub user_id: i64,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    pub text: String,
+}
+
+/// One `/msg` reply produced while replaying a fixture, in the order it was
+/// generated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayReply {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub reply: String,
+}
+
+/// A chat's [`ALL_KEY`] chain shape at the end of a replay, for regression
+/// comparisons.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatChainStats {
+    pub transition_count: u64,
+    pub entropy: Option<EntropyReport>,
+}
+
+/// The full result of replaying a fixture: every `/msg` reply produced, in
+/// order, plus each chat's final chain stats.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayTranscript {
+    pub replies: Vec<ReplayReply>,
+    pub chain_stats: HashMap<i64, ChatChainStats>,
+}
+
+/// Parses a fixture's JSONL text into events, one per non-blank line.
+pub fn parse_fixture(jsonl: &str) -> Result<Vec<ReplayEvent>, String> {
+    jsonl
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| serde_json::from_str(line).map_err(|err| format!("fixture line {}: {err}", i + 1)))
+        .collect()
+}
+
+/// Replays `events` against `storage` in order: text starting with `/msg` is
+/// run through the real `/msg` parse-and-generate path and recorded as a
+/// [`ReplayReply`]; everything else is learned from, gated by the chat's
+/// consent policy exactly as the live message path gates it. Each event's
+/// position in `events` is used as its learn-journal message ID.
+pub async fn run_replay(storage: &dyn Storage, events: &[ReplayEvent]) -> ReplayTranscript {
+    let mut transcript = ReplayTranscript::default();
+    let perf_tracker = PerfTracker::new();
+    let mut chats_seen = Vec::new();
+
+    for (index, event) in events.iter().enumerate() {
+        if !chats_seen.contains(&event.chat_id) {
+            chats_seen.push(event.chat_id);
+        }
+
+        if event.username.is_some() || event.first_name.is_some() {
+            let info = UserInfo {
+                chat_id: event.chat_id,
+                user_id: event.user_id,
+                username: event.username.clone(),
+                first_name: event.first_name.clone().unwrap_or_default(),
+                last_seen: chrono::Utc::now().timestamp(),
+            };
+            if let Err(err) = storage.put_user_info(&info).await {
+                log::error!("replay: failed to save user info for {}: {err}", event.user_id);
+            }
+        }
+
+        if let Some(args) = event.text.strip_prefix("/msg") {
+            let reply = match parse_msg_command_params(storage, event.chat_id, args.trim(), None, Some(event.user_id), None).await {
+                Ok(params) => do_msg_command(storage, event.chat_id, &params, 0, &perf_tracker).await.text,
+                Err(err) => format!("error: {err}"),
+            };
+            transcript.replies.push(ReplayReply { chat_id: event.chat_id, user_id: event.user_id, reply });
+            continue;
+        }
+
+        // Fixtures carry no wall-clock time, so freezes are checked as of the
+        // Unix epoch; a fixture wanting to exercise `/freeze` expiry would
+        // need its own event-level clock, which is out of scope here.
+        if is_message_learning_allowed(storage, event.chat_id, event.user_id, 0).await.is_allowed() {
+            let message_id = index as i64;
+            if let Err(err) = learn_with_journal(storage, event.chat_id, message_id, event.user_id, &event.text).await {
+                log::error!("replay: failed to learn fixture line {}: {err}", index + 1);
+            }
+        }
+    }
+
+    for chat_id in chats_seen {
+        let all_chain = match storage.read_chat_data(chat_id).await {
+            Ok(Some(chat_data)) => chat_data.data.get(ALL_KEY).cloned(),
+            _ => None,
+        };
+        let stats = ChatChainStats {
+            transition_count: all_chain.as_ref().map(|chain| chain.transition_count()).unwrap_or(0),
+            entropy: all_chain.as_ref().and_then(|chain| chain.entropy_report()),
+        };
+        transcript.chain_stats.insert(chat_id, stats);
+    }
+
+    transcript
+}
+
+/// Compares `actual` against `expected`, returning a human-readable
+/// description of each difference (empty if they match). Backs the `replay
+/// --assert` CLI mode's golden-file regression check.
+pub fn diff_transcripts(actual: &ReplayTranscript, expected: &ReplayTranscript) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    if actual.replies != expected.replies {
+        mismatches.push(format!("replies differ:\n  actual:   {:?}\n  expected: {:?}", actual.replies, expected.replies));
+    }
+
+    for (chat_id, expected_stats) in &expected.chain_stats {
+        match actual.chain_stats.get(chat_id) {
+            Some(actual_stats) if actual_stats == expected_stats => {}
+            Some(actual_stats) => mismatches.push(format!(
+                "chain stats for chat {chat_id} differ:\n  actual:   {actual_stats:?}\n  expected: {expected_stats:?}"
+            )),
+            None => mismatches.push(format!("chat {chat_id} is missing from the replay's chain stats")),
+        }
+    }
+    for chat_id in actual.chain_stats.keys() {
+        if !expected.chain_stats.contains_key(chat_id) {
+            mismatches.push(format!("chat {chat_id} has chain stats that weren't in the golden file"));
+        }
+    }
+
+    mismatches
+}
+
+/// Runs the `replay` CLI subcommand: `replay --fixture <fixture.jsonl>
+/// [--assert <golden.json>]`. With no `--assert`, prints the transcript as
+/// pretty JSON. With `--assert`, diffs against the golden file and prints any
+/// mismatches instead. Exits the process with a nonzero code on any failure,
+/// so it can be wired into CI as a plain shell step.
+pub async fn run_replay_cli(args: &[String]) {
+    let mut fixture_path = None;
+    let mut assert_path = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--fixture" => fixture_path = iter.next().cloned(),
+            "--assert" => assert_path = iter.next().cloned(),
+            other => {
+                eprintln!("replay: unrecognized argument \"{other}\"");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let Some(fixture_path) = fixture_path else {
+        eprintln!("Usage: replay --fixture <fixture.jsonl> [--assert <golden.json>]");
+        std::process::exit(2);
+    };
+
+    let jsonl = match fs::read_to_string(&fixture_path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("replay: failed to read fixture {fixture_path}: {err}");
+            std::process::exit(2);
+        }
+    };
+    let events = match parse_fixture(&jsonl) {
+        Ok(events) => events,
+        Err(err) => {
+            eprintln!("replay: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    let storage = InMemoryStorage::new();
+    let transcript = run_replay(&storage, &events).await;
+
+    match assert_path {
+        None => match serde_json::to_string_pretty(&transcript) {
+            Ok(json) => println!("{json}"),
+            Err(err) => {
+                eprintln!("replay: failed to render the transcript as JSON: {err}");
+                std::process::exit(1);
+            }
+        },
+        Some(golden_path) => {
+            let golden_json = match fs::read_to_string(&golden_path) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    eprintln!("replay: failed to read golden file {golden_path}: {err}");
+                    std::process::exit(2);
+                }
+            };
+            let expected: ReplayTranscript = match serde_json::from_str(&golden_json) {
+                Ok(expected) => expected,
+                Err(err) => {
+                    eprintln!("replay: failed to parse golden file {golden_path}: {err}");
+                    std::process::exit(2);
+                }
+            };
+
+            let mismatches = diff_transcripts(&transcript, &expected);
+            if mismatches.is_empty() {
+                println!("replay matches {golden_path}");
+            } else {
+                for mismatch in &mismatches {
+                    eprintln!("{mismatch}");
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fixture_skips_blank_lines_and_reports_the_bad_line_number() {
+        let events = parse_fixture(
+            "{\"chat_id\": 1, \"user_id\": 42, \"text\": \"hi\"}\n\n{\"chat_id\": 1, \"user_id\": 42, \"text\": \"there\"}\n",
+        )
+        .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].text, "hi");
+        assert_eq!(events[1].text, "there");
+
+        let err = parse_fixture("{\"chat_id\": 1, \"user_id\": 42, \"text\": \"hi\"}\nnot json\n").unwrap_err();
+        assert!(err.contains("line 2"), "expected the second line to be blamed, got: {err}");
+    }
+
+    #[tokio::test]
+    async fn run_replay_learns_from_plain_text_and_replies_to_msg_commands() {
+        let storage = InMemoryStorage::new();
+        let events = vec![
+            ReplayEvent { chat_id: 1, user_id: 42, username: None, first_name: None, text: "hello there friend".to_string() },
+            ReplayEvent { chat_id: 1, user_id: 42, username: None, first_name: None, text: "/msg hello".to_string() },
+        ];
+
+        let transcript = run_replay(&storage, &events).await;
+        assert_eq!(transcript.replies.len(), 1);
+        assert_eq!(transcript.replies[0].reply, "hello there friend");
+        assert!(transcript.chain_stats[&1].transition_count > 0);
+    }
+
+    #[tokio::test]
+    async fn run_replay_registers_user_info_so_later_mentions_resolve() {
+        let storage = InMemoryStorage::new();
+        let events = vec![
+            ReplayEvent {
+                chat_id: 1,
+                user_id: 99,
+                username: Some("dave".to_string()),
+                first_name: Some("Dave".to_string()),
+                text: "hi everyone".to_string(),
+            },
+            ReplayEvent { chat_id: 1, user_id: 1, username: None, first_name: None, text: "/msg @dave".to_string() },
+        ];
+
+        let transcript = run_replay(&storage, &events).await;
+        assert_eq!(transcript.replies[0].reply, "hi everyone");
+    }
+
+    #[tokio::test]
+    async fn run_replay_respects_opt_in_learning_policy() {
+        let storage = InMemoryStorage::new();
+        let mut settings = storage.get_chat_settings(1).await.unwrap();
+        settings.learning_policy = crate::auto_prune::LearningPolicy::OptIn;
+        storage.put_chat_settings(1, &settings).await.unwrap();
+
+        let events = vec![ReplayEvent { chat_id: 1, user_id: 42, username: None, first_name: None, text: "never learned".to_string() }];
+        let transcript = run_replay(&storage, &events).await;
+        assert_eq!(transcript.chain_stats[&1].transition_count, 0);
+    }
+
+    #[tokio::test]
+    async fn diff_transcripts_reports_reply_and_chain_stat_mismatches() {
+        let storage = InMemoryStorage::new();
+        let events = vec![
+            ReplayEvent { chat_id: 1, user_id: 42, username: None, first_name: None, text: "hello there".to_string() },
+            ReplayEvent { chat_id: 1, user_id: 42, username: None, first_name: None, text: "/msg hello".to_string() },
+        ];
+        let actual = run_replay(&storage, &events).await;
+
+        assert!(diff_transcripts(&actual, &actual).is_empty());
+
+        let mut expected = actual.clone();
+        expected.replies[0].reply = "something else".to_string();
+        let mismatches = diff_transcripts(&actual, &expected);
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].contains("replies differ"));
+    }
+}
@@ -0,0 +1,285 @@
+//! A tiny embedded scheduler shared by `main.rs`'s timed background jobs
+//! (auto-prune, journal recovery, dead-letter retry, `gc-users`, import
+//! contribution expiry, stats export), so a job that would otherwise wait a
+//! full fresh [`tokio::time::interval`] period before its first post-restart
+//! run instead fires right away if enough wall-clock time has already passed
+//! since it last completed.
+//!
+//! Each job's last-run time is persisted via [`Storage::get_job_last_run`]/
+//! [`Storage::put_job_last_run`], so the catch-up semantics survive a process
+//! restart rather than just a single [`tokio::time::interval`]'s lifetime.
+//! Only one catch-up run ever fires per gap, since [`Scheduler::run_job`]
+//! stamps `last_run_unix` to the time the job actually ran, not to whatever
+//! time it was originally due.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::markov_telegram_bot::Storage;
+
+/// Whether a job with the given `cadence` is due to run again, given when it
+/// last completed (`None` if it never has) and the current time. Extracted as
+/// a pure function so the catch-up semantics can be tested without real time
+/// or an async runtime.
+pub fn is_due(cadence: Duration, last_run_unix: Option<i64>, now_unix: i64) -> bool {
+    match last_run_unix {
+        None => true,
+        Some(last_run_unix) => now_unix.saturating_sub(last_run_unix) >= cadence.as_secs() as i64,
+    }
+}
+
+/// A single job's in-process bookkeeping.
+#[derive(Debug, Default)]
+struct JobState {
+    last_run_unix: Option<i64>,
+    last_error: Option<String>,
+    running: bool,
+}
+
+/// A single job's current status, for `/status` reporting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub last_run_unix: Option<i64>,
+    pub last_error: Option<String>,
+    pub running: bool,
+}
+
+/// Tracks last-run times and in-flight status for every registered timed job,
+/// shared between `main`'s schedule loops and `/status`. See the module docs
+/// for why jobs persist their last-run time rather than relying solely on
+/// [`tokio::time::interval`].
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Mutex<HashMap<&'static str, JobState>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `job` if `name` is due against `cadence`, reading its persisted
+    /// last-run time from `storage` the first time this process checks it and
+    /// caching that in-process afterward. Skips the run (without touching
+    /// `storage`) if a previous call for the same `name` is still in flight,
+    /// or if it isn't due yet. On success, stamps `now_unix` as `name`'s new
+    /// last-run time, both in-process and in `storage`; on failure, records
+    /// the error for `/status` but leaves the last-run time untouched, so a
+    /// failed run is retried on the next tick rather than waiting a full
+    /// `cadence` again.
+    pub async fn run_job<F, Fut>(&self, storage: &dyn Storage, name: &'static str, cadence: Duration, now_unix: i64, job: F)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        let cached_last_run = self.jobs.lock().unwrap().get(name).map(|state| state.last_run_unix);
+
+        let last_run_unix = match cached_last_run {
+            Some(last_run_unix) => last_run_unix,
+            None => match storage.get_job_last_run(name).await {
+                Ok(last_run_unix) => last_run_unix,
+                Err(err) => {
+                    log::error!("failed to read last run time for job {name}: {err}");
+                    None
+                }
+            },
+        };
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let state = jobs.entry(name).or_default();
+            state.last_run_unix = last_run_unix;
+            if state.running || !is_due(cadence, state.last_run_unix, now_unix) {
+                return;
+            }
+            state.running = true;
+        }
+
+        let result = job().await;
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let state = jobs.entry(name).or_default();
+            state.running = false;
+            match &result {
+                Ok(()) => {
+                    state.last_run_unix = Some(now_unix);
+                    state.last_error = None;
+                }
+                Err(err) => state.last_error = Some(err.clone()),
+            }
+        }
+
+        match result {
+            Ok(()) => {
+                if let Err(err) = storage.put_job_last_run(name, now_unix).await {
+                    log::error!("failed to persist last run time for job {name}: {err}");
+                }
+            }
+            Err(err) => log::error!("job {name} failed: {err}"),
+        }
+    }
+
+    /// Returns every registered job's current status, sorted by name.
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut statuses: Vec<JobStatus> = jobs
+            .iter()
+            .map(|(&name, state)| JobStatus {
+                name,
+                last_run_unix: state.last_run_unix,
+                last_error: state.last_error.clone(),
+                running: state.running,
+            })
+            .collect();
+        statuses.sort_unstable_by_key(|status| status.name);
+        statuses
+    }
+
+    /// Formats every registered job's status into a compact report line,
+    /// matching [`crate::health::HealthState::report`]'s style, for
+    /// `/status` to append after the health report.
+    pub fn report(&self) -> String {
+        let statuses = self.statuses();
+        if statuses.is_empty() {
+            return "Jobs: none registered yet".to_string();
+        }
+
+        let jobs = statuses
+            .into_iter()
+            .map(|status| {
+                let last_run = status.last_run_unix.map_or_else(|| "never".to_string(), |ts| ts.to_string());
+                format!(
+                    "{}(last run: {last_run}{}{})",
+                    status.name,
+                    if status.running { ", running" } else { "" },
+                    status.last_error.map_or_else(String::new, |err| format!(", last error: {err}")),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Jobs: {jobs}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markov_telegram_bot::InMemoryStorage;
+
+    #[test]
+    fn is_due_when_never_run() {
+        assert!(is_due(Duration::from_secs(60), None, 1_000));
+    }
+
+    #[test]
+    fn is_due_is_false_just_after_running() {
+        assert!(!is_due(Duration::from_secs(60), Some(1_000), 1_001));
+    }
+
+    #[test]
+    fn is_due_is_false_just_under_the_cadence() {
+        assert!(!is_due(Duration::from_secs(60), Some(1_000), 1_059));
+    }
+
+    #[test]
+    fn is_due_is_true_exactly_at_the_cadence() {
+        assert!(is_due(Duration::from_secs(60), Some(1_000), 1_060));
+    }
+
+    #[test]
+    fn is_due_is_true_just_over_the_cadence() {
+        assert!(is_due(Duration::from_secs(60), Some(1_000), 1_061));
+    }
+
+    #[tokio::test]
+    async fn run_job_runs_immediately_when_never_run_before() {
+        let storage = InMemoryStorage::default();
+        let scheduler = Scheduler::new();
+
+        scheduler.run_job(&storage, "test_job", Duration::from_secs(60), 1_000, || async { Ok(()) }).await;
+
+        let statuses = scheduler.statuses();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].last_run_unix, Some(1_000));
+        assert_eq!(storage.get_job_last_run("test_job").await.unwrap(), Some(1_000));
+    }
+
+    #[tokio::test]
+    async fn run_job_skips_a_second_run_within_the_same_cadence() {
+        let storage = InMemoryStorage::default();
+        let scheduler = Scheduler::new();
+
+        scheduler.run_job(&storage, "test_job", Duration::from_secs(60), 1_000, || async { Ok(()) }).await;
+        scheduler.run_job(&storage, "test_job", Duration::from_secs(60), 1_010, || async { panic!("should not run") }).await;
+
+        assert_eq!(scheduler.statuses()[0].last_run_unix, Some(1_000));
+    }
+
+    #[tokio::test]
+    async fn run_job_fires_at_most_one_catch_up_run_after_downtime() {
+        let storage = InMemoryStorage::default();
+        storage.put_job_last_run("test_job", 1_000).await.unwrap();
+
+        // A fresh `Scheduler` simulates a restart: it has no in-process
+        // memory of the job, only what's in `storage` from before the
+        // "downtime" - here, ten cadence periods' worth.
+        let scheduler = Scheduler::new();
+        let cadence = Duration::from_secs(60);
+        let now = 1_000 + 10 * 60;
+
+        let run_count = std::cell::Cell::new(0usize);
+        scheduler
+            .run_job(&storage, "test_job", cadence, now, || async {
+                run_count.set(run_count.get() + 1);
+                Ok(())
+            })
+            .await;
+        // A second tick at the same `now` (as would happen if the interval
+        // fired again before the job's own last-run time moved far enough to
+        // matter) must not fire again.
+        scheduler
+            .run_job(&storage, "test_job", cadence, now, || async {
+                run_count.set(run_count.get() + 1);
+                Ok(())
+            })
+            .await;
+
+        assert_eq!(run_count.get(), 1);
+        assert_eq!(storage.get_job_last_run("test_job").await.unwrap(), Some(now));
+    }
+
+    #[tokio::test]
+    async fn run_job_leaves_the_last_run_time_untouched_on_failure() {
+        let storage = InMemoryStorage::default();
+        let scheduler = Scheduler::new();
+
+        scheduler.run_job(&storage, "test_job", Duration::from_secs(60), 1_000, || async { Err("boom".to_string()) }).await;
+
+        assert_eq!(storage.get_job_last_run("test_job").await.unwrap(), None);
+        let statuses = scheduler.statuses();
+        assert_eq!(statuses[0].last_run_unix, None);
+        assert_eq!(statuses[0].last_error.as_deref(), Some("boom"));
+
+        // Since the last-run time is unchanged, the job is immediately due
+        // again on the very next tick.
+        assert!(is_due(Duration::from_secs(60), statuses[0].last_run_unix, 1_000));
+    }
+
+    #[test]
+    fn report_says_none_registered_when_empty() {
+        assert_eq!(Scheduler::new().report(), "Jobs: none registered yet");
+    }
+
+    #[tokio::test]
+    async fn report_includes_a_completed_job() {
+        let storage = InMemoryStorage::default();
+        let scheduler = Scheduler::new();
+        scheduler.run_job(&storage, "test_job", Duration::from_secs(60), 1_000, || async { Ok(()) }).await;
+
+        assert!(scheduler.report().contains("test_job(last run: 1000)"));
+    }
+}
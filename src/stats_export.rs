@@ -0,0 +1,218 @@
+//! Nightly per-chat statistics export to CSV, so vocabulary growth can be
+//! analyzed offline without direct database access.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::markov_telegram_bot::{ChatData, ALL_KEY};
+
+/// Tracks messages learned per chat since the last export, so the nightly
+/// stats job can report a daily count without re-deriving it from chain
+/// contents (which only grow monotonically and can't tell "today" apart from
+/// "ever").
+#[derive(Default)]
+pub struct ActivityCounters {
+    counts: Mutex<HashMap<i64, u64>>,
+}
+
+impl ActivityCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that a message was learned in `chat_id`.
+    pub fn record_message(&self, chat_id: i64) {
+        *self.counts.lock().unwrap().entry(chat_id).or_insert(0) += 1;
+    }
+
+    /// Returns and resets the count accumulated for `chat_id`.
+    pub fn take_count(&self, chat_id: i64) -> u64 {
+        self.counts.lock().unwrap().remove(&chat_id).unwrap_or(0)
+    }
+}
+
+const CSV_HEADER: &str = "date,chat_id,users,transitions,distinct_words,messages_learned";
+
+/// One day's worth of statistics for a single chat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatStatsRow {
+    pub date: String,
+    pub chat_id: i64,
+    pub users: usize,
+    pub transitions: u64,
+    pub distinct_words: usize,
+    pub messages_learned: u64,
+}
+
+impl ChatStatsRow {
+    /// Summarizes a chat's current chain data. `messages_learned` is the
+    /// caller-supplied count of messages learned since the last export.
+    pub fn from_chat_data(date: &str, chat_id: i64, chat_data: &ChatData, messages_learned: u64) -> Self {
+        let users = chat_data.data.keys().filter(|key| key.as_str() != ALL_KEY).count();
+        let (transitions, distinct_words) = chat_data
+            .data
+            .get(ALL_KEY)
+            .map(|chain| (chain.transition_count(), chain.meta_counts().len()))
+            .unwrap_or_default();
+        Self {
+            date: date.to_string(),
+            chat_id,
+            users,
+            transitions,
+            distinct_words,
+            messages_learned,
+        }
+    }
+
+    fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.date, self.chat_id, self.users, self.transitions, self.distinct_words, self.messages_learned
+        )
+    }
+}
+
+/// Appends `row` to the per-chat CSV file under `dir`, creating the file
+/// (with a header) if it doesn't exist yet. If a row for `row.date` has
+/// already been written, does nothing, so the maintenance job can safely run
+/// twice in a day without duplicating rows.
+///
+/// Guards against concurrent runs with a simple exclusive lock file alongside
+/// the CSV; a run that can't acquire the lock within a short window gives up.
+pub fn append_chat_stats_row(dir: &Path, row: &ChatStatsRow) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let csv_path = dir.join(format!("{}.csv", row.chat_id));
+    let lock_path = dir.join(format!("{}.csv.lock", row.chat_id));
+
+    let _lock = acquire_lock(&lock_path)?;
+
+    if csv_path.exists() && date_already_recorded(&csv_path, &row.date)? {
+        return Ok(());
+    }
+
+    let is_new = !csv_path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(&csv_path)?;
+    if is_new {
+        writeln!(file, "{CSV_HEADER}")?;
+    }
+    writeln!(file, "{}", row.to_csv_line())
+}
+
+fn date_already_recorded(csv_path: &Path, date: &str) -> io::Result<bool> {
+    let file = fs::File::open(csv_path)?;
+    for line in io::BufReader::new(file).lines() {
+        if line?.split(',').next() == Some(date) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// An exclusive lock held for the guard's lifetime, implemented as a file
+/// created with `create_new` so only one process can hold it at a time.
+/// Removed on drop.
+struct LockGuard {
+    path: PathBuf,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn acquire_lock(lock_path: &Path) -> io::Result<LockGuard> {
+    const MAX_ATTEMPTS: usize = 50;
+    const RETRY_DELAY: Duration = Duration::from_millis(20);
+
+    for _ in 0..MAX_ATTEMPTS {
+        match OpenOptions::new().write(true).create_new(true).open(lock_path) {
+            Ok(_) => return Ok(LockGuard { path: lock_path.to_path_buf() }),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => thread::sleep(RETRY_DELAY),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::WouldBlock, "timed out waiting for stats export lock"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markov_chain::TripletMarkovChain;
+    use std::collections::HashMap;
+
+    fn sample_row(date: &str) -> ChatStatsRow {
+        ChatStatsRow {
+            date: date.to_string(),
+            chat_id: 42,
+            users: 2,
+            transitions: 10,
+            distinct_words: 5,
+            messages_learned: 3,
+        }
+    }
+
+    #[test]
+    fn writes_header_and_row_for_a_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        append_chat_stats_row(dir.path(), &sample_row("2024-01-01")).unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("42.csv")).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        assert_eq!(lines.next(), Some(sample_row("2024-01-01").to_csv_line().as_str()));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn running_twice_in_one_day_does_not_duplicate_the_row() {
+        let dir = tempfile::tempdir().unwrap();
+        append_chat_stats_row(dir.path(), &sample_row("2024-01-01")).unwrap();
+        append_chat_stats_row(dir.path(), &sample_row("2024-01-01")).unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("42.csv")).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+    }
+
+    #[test]
+    fn a_new_day_appends_a_second_row() {
+        let dir = tempfile::tempdir().unwrap();
+        append_chat_stats_row(dir.path(), &sample_row("2024-01-01")).unwrap();
+        append_chat_stats_row(dir.path(), &sample_row("2024-01-02")).unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("42.csv")).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+    }
+
+    #[test]
+    fn activity_counters_track_and_reset_per_chat() {
+        let counters = ActivityCounters::new();
+        counters.record_message(1);
+        counters.record_message(1);
+        counters.record_message(2);
+
+        assert_eq!(counters.take_count(1), 2);
+        assert_eq!(counters.take_count(1), 0);
+        assert_eq!(counters.take_count(2), 1);
+    }
+
+    #[test]
+    fn from_chat_data_summarizes_users_and_vocabulary() {
+        let mut all_chain = TripletMarkovChain::new();
+        all_chain.add_message("hello world");
+        let mut data = HashMap::new();
+        data.insert(ALL_KEY.to_string(), all_chain);
+        data.insert("123".to_string(), TripletMarkovChain::new());
+        let chat_data =
+            ChatData { data, word_index: None, migrated_from_legacy: false, live_learned_id_range: None, owner_bot_id: None };
+
+        let row = ChatStatsRow::from_chat_data("2024-01-01", 42, &chat_data, 1);
+        assert_eq!(row.users, 1);
+        assert_eq!(row.distinct_words, 2);
+    }
+}
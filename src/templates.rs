@@ -0,0 +1,185 @@
+//! Per-chat overrides for a whitelisted set of canned bot replies, set via
+//! `/settemplate <key> "<text>"` and cleared with `/settemplate reset <key>`.
+//! There's no i18n layer in this bot (replies are plain string literals
+//! inline in each `do_*_command`), so "override the language table" becomes
+//! "each whitelisted reply site checks [`ChatSettings::reply_templates`]
+//! before falling back to its own literal".
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The canned replies admins are allowed to override. Adding a new one here
+/// also means wiring the corresponding reply site to call [`render_template`]
+/// with `ChatSettings::reply_templates` before falling back to its default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKey {
+    /// "I haven't learned anything from that source yet." (`/msg`).
+    NoData,
+    /// "I've forgotten everything I learned from you in this chat." (`/deleteme`).
+    DeleteConfirmation,
+    /// Reserved for a future rate-limiting cooldown reply; not yet sent by
+    /// any command, but accepted here so admins can pre-configure it.
+    Cooldown,
+}
+
+impl TemplateKey {
+    const ALL: [TemplateKey; 3] = [TemplateKey::NoData, TemplateKey::DeleteConfirmation, TemplateKey::Cooldown];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            TemplateKey::NoData => "no_data",
+            TemplateKey::DeleteConfirmation => "delete_confirmation",
+            TemplateKey::Cooldown => "cooldown",
+        }
+    }
+
+    fn parse(key: &str) -> Option<TemplateKey> {
+        TemplateKey::ALL.into_iter().find(|candidate| candidate.as_str() == key)
+    }
+
+    /// The reply's built-in text, used when a chat has no override.
+    pub fn default_text(self) -> &'static str {
+        match self {
+            TemplateKey::NoData => "I haven't learned anything from that source yet.",
+            TemplateKey::DeleteConfirmation => "I've forgotten everything I learned from you in this chat.",
+            TemplateKey::Cooldown => "Slow down a bit before trying that again.",
+        }
+    }
+}
+
+impl fmt::Display for TemplateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Placeholders any template may use, substituted from whatever values the
+/// calling reply site has on hand (a placeholder with no value at render
+/// time is left blank rather than treated as an error; that's caught earlier,
+/// at set-time, by [`validate_template_text`]).
+const ALLOWED_PLACEHOLDERS: [&str; 3] = ["user", "seed", "count"];
+
+/// Checks that `text` only references placeholders from
+/// [`ALLOWED_PLACEHOLDERS`], returning the first unknown one found.
+pub fn validate_template_text(text: &str) -> Result<(), String> {
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else { break };
+        let placeholder = &rest[open + 1..open + close];
+        if !ALLOWED_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "Unknown placeholder {{{placeholder}}}. Allowed placeholders: {}.",
+                ALLOWED_PLACEHOLDERS.map(|name| format!("{{{name}}}")).join(", ")
+            ));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    Ok(())
+}
+
+/// Substitutes `{name}` placeholders in `text` with the corresponding entry
+/// from `values`, leaving unrecognized or unfilled placeholders untouched.
+pub fn render_template(text: &str, values: &HashMap<&str, String>) -> String {
+    let mut rendered = text.to_string();
+    for (name, value) in values {
+        rendered = rendered.replace(&format!("{{{name}}}"), value);
+    }
+    rendered
+}
+
+/// Looks up `key`'s effective text for a chat: the chat's override if one is
+/// set, otherwise the built-in default; then renders it with `values`.
+pub fn render(key: TemplateKey, overrides: &HashMap<String, String>, values: &HashMap<&str, String>) -> String {
+    let text = overrides.get(key.as_str()).map(String::as_str).unwrap_or_else(|| key.default_text());
+    render_template(text, values)
+}
+
+/// A parsed `/settemplate` invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetTemplateRequest {
+    Set { key: TemplateKey, text: String },
+    Reset { key: TemplateKey },
+}
+
+/// Parses `/settemplate` arguments: `<key> "<text>"` or `reset <key>`.
+pub fn parse_set_template_args(args: &str) -> Result<SetTemplateRequest, String> {
+    let usage = || {
+        format!(
+            "Usage: /settemplate <key> \"<text>\", or /settemplate reset <key>. Keys: {}.",
+            TemplateKey::ALL.map(|key| key.as_str()).join(", ")
+        )
+    };
+
+    let (first, rest) = args.trim().split_once(char::is_whitespace).ok_or_else(usage)?;
+    let rest = rest.trim();
+
+    if first.eq_ignore_ascii_case("reset") {
+        let key = TemplateKey::parse(rest).ok_or_else(usage)?;
+        return Ok(SetTemplateRequest::Reset { key });
+    }
+
+    let key = TemplateKey::parse(first).ok_or_else(usage)?;
+    let text = rest.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).ok_or_else(usage)?;
+    if text.is_empty() {
+        return Err(usage());
+    }
+    validate_template_text(text)?;
+
+    Ok(SetTemplateRequest::Set { key, text: text.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_set_template_rejects_unknown_keys() {
+        assert!(parse_set_template_args("bogus \"hi\"").is_err());
+        assert!(parse_set_template_args("reset bogus").is_err());
+    }
+
+    #[test]
+    fn parse_set_template_requires_quoted_text() {
+        assert!(parse_set_template_args("no_data hi").is_err());
+    }
+
+    #[test]
+    fn parse_set_template_parses_set_and_reset() {
+        assert_eq!(
+            parse_set_template_args("no_data \"we know nothing yet\"").unwrap(),
+            SetTemplateRequest::Set { key: TemplateKey::NoData, text: "we know nothing yet".to_string() }
+        );
+        assert_eq!(
+            parse_set_template_args("reset no_data").unwrap(),
+            SetTemplateRequest::Reset { key: TemplateKey::NoData }
+        );
+    }
+
+    #[test]
+    fn validate_template_text_rejects_unknown_placeholders() {
+        assert!(validate_template_text("hello {user}").is_ok());
+        assert!(validate_template_text("hello {username}").is_err());
+    }
+
+    #[test]
+    fn set_template_rejects_text_with_unknown_placeholders() {
+        let err = parse_set_template_args("no_data \"hi {username}\"").unwrap_err();
+        assert!(err.contains("username"));
+    }
+
+    #[test]
+    fn render_template_substitutes_known_placeholders_and_leaves_others() {
+        let mut values = HashMap::new();
+        values.insert("user", "dave".to_string());
+        assert_eq!(render_template("hi {user}, seed was {seed}", &values), "hi dave, seed was {seed}");
+    }
+
+    #[test]
+    fn render_uses_override_then_falls_back_to_default() {
+        let mut overrides = HashMap::new();
+        assert_eq!(render(TemplateKey::NoData, &overrides, &HashMap::new()), TemplateKey::NoData.default_text());
+
+        overrides.insert("no_data".to_string(), "we know nothing yet".to_string());
+        assert_eq!(render(TemplateKey::NoData, &overrides, &HashMap::new()), "we know nothing yet");
+    }
+}
@@ -0,0 +1,76 @@
+//! Shared test-only fixtures for building [`TripletMarkovChain`]s and
+//! asserting on their learned transitions, so individual test modules don't
+//! each hand-roll their own version of the `chain_with`/`oversized_chain`-
+//! style helper that used to be copied into every file that needed one. The
+//! chain's internal `data`/`meta` layout can change (interning, a higher
+//! chain order, ...) without every caller needing to be rewritten, since
+//! everything here only touches [`TripletMarkovChain`]'s public API.
+//!
+//! Only compiled in for tests; there's no runtime use for any of this.
+
+use crate::markov_chain::{Counter, TripletMarkovChain};
+
+/// Builds a [`TripletMarkovChain`] fixture from a sequence of learned
+/// messages.
+#[derive(Debug, Default)]
+pub struct ChainBuilder {
+    chain: TripletMarkovChain,
+}
+
+impl ChainBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Learns one message.
+    pub fn msg(mut self, text: &str) -> Self {
+        self.chain.add_message(text);
+        self
+    }
+
+    /// Learns each message in `texts`, in order.
+    pub fn msgs(mut self, texts: &[&str]) -> Self {
+        for text in texts {
+            self.chain.add_message(text);
+        }
+        self
+    }
+
+    /// Learns `text` `count` times, for fixtures that need a transition to
+    /// dominate (or a specific count to assert against) rather than merely
+    /// be possible.
+    pub fn weighted_msg(mut self, text: &str, count: u32) -> Self {
+        for _ in 0..count {
+            self.chain.add_message(text);
+        }
+        self
+    }
+
+    pub fn build(self) -> TripletMarkovChain {
+        self.chain
+    }
+}
+
+/// Asserts that `chain` has learned `w3` following the pair `(w1, w2)`
+/// exactly `count` times.
+#[track_caller]
+pub fn assert_transition(chain: &TripletMarkovChain, w1: &str, w2: &str, w3: &str, count: Counter) {
+    assert_eq!(
+        chain.triplet_count(w1, w2, w3),
+        count,
+        "expected {w1:?} {w2:?} -> {w3:?} to have been learned {count} time(s)"
+    );
+}
+
+/// Generates from `chain` repeatedly and asserts every result is one of
+/// `expected` - i.e. that `expected` is the complete set of reachable
+/// outputs, not just that each is individually reachable. Retries generously
+/// since generation is randomized.
+#[track_caller]
+pub fn assert_generates_only(chain: &TripletMarkovChain, seed: Option<&str>, expected: &[&str]) {
+    const ATTEMPTS: usize = 200;
+    for _ in 0..ATTEMPTS {
+        let message = chain.generate(seed, None, None).expect("expected generation to succeed");
+        assert!(expected.contains(&message.as_str()), "generated {message:?}, which isn't in {expected:?}");
+    }
+}
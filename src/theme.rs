@@ -0,0 +1,150 @@
+//! Time-boxed "themed mode": for a limited time, generation for a chat is
+//! biased toward a chosen topic word, falling silently back to normal
+//! generation wherever the active chain doesn't know it. Unlike auto-prune's
+//! [`crate::auto_prune::ChatSettings`] fields, a theme carries its own
+//! expiry rather than being a simple on/off toggle.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::markov_chain::{GeneratedMessage, LengthRequirement, MarkovChainError, TripletMarkovChain};
+
+/// A chat's active theme: bias generation toward `word` until
+/// `expires_at_unix` (Unix seconds).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThemeSettings {
+    pub word: String,
+    pub expires_at_unix: i64,
+}
+
+impl ThemeSettings {
+    /// Whether this theme is still active at `now_unix`. Takes the current
+    /// time as a parameter (rather than reading the clock itself) so tests
+    /// can exercise expiry without depending on real time.
+    pub fn is_active(&self, now_unix: i64) -> bool {
+        now_unix < self.expires_at_unix
+    }
+}
+
+/// Generates a message from `chain`, wrapping the plain [`TripletMarkovChain::generate`]
+/// call with theme bias: if no explicit `seed` was given and `theme` is
+/// active, tries seeding with the theme word first, falling back to normal
+/// generation if the chain doesn't know that word. An explicit `seed`
+/// always takes precedence over the theme.
+pub fn generate_themed(
+    chain: &TripletMarkovChain,
+    seed: Option<&str>,
+    length_requirement: Option<LengthRequirement>,
+    theme: Option<&ThemeSettings>,
+    now_unix: i64,
+) -> Result<String, MarkovChainError> {
+    generate_themed_with_rng(chain, seed, length_requirement, theme, now_unix, false, &mut rand::rng()).map(|message| message.text)
+}
+
+/// Like [`generate_themed`], but draws from a caller-supplied RNG instead of
+/// the thread-local one (so a test can reproduce an exact generation by
+/// seeding a deterministic RNG), takes `allow_fallback` to opt into
+/// [`TripletMarkovChain::generate_with_rng`]'s bigram fallback, and reports
+/// whether that fallback was used.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_themed_with_rng(
+    chain: &TripletMarkovChain,
+    seed: Option<&str>,
+    length_requirement: Option<LengthRequirement>,
+    theme: Option<&ThemeSettings>,
+    now_unix: i64,
+    allow_fallback: bool,
+    rng: &mut impl Rng,
+) -> Result<GeneratedMessage, MarkovChainError> {
+    if seed.is_none() {
+        if let Some(theme) = theme.filter(|theme| theme.is_active(now_unix)) {
+            match chain.generate_with_rng(Some(&theme.word), length_requirement, allow_fallback, None, rng) {
+                Err(MarkovChainError::NoSuchSeed(_)) => {}
+                result => return result,
+            }
+        }
+    }
+    chain.generate_with_rng(seed, length_requirement, allow_fallback, None, rng)
+}
+
+/// Parses a duration token like `24h`, `30m`, `2d`, or a bare number of
+/// seconds, into a number of seconds.
+pub fn parse_duration_secs(token: &str) -> Option<i64> {
+    let (number, unit_secs) = match token.strip_suffix('d') {
+        Some(rest) => (rest, 86_400),
+        None => match token.strip_suffix('h') {
+            Some(rest) => (rest, 3_600),
+            None => match token.strip_suffix('m') {
+                Some(rest) => (rest, 60),
+                None => match token.strip_suffix('s') {
+                    Some(rest) => (rest, 1),
+                    None => (token, 1),
+                },
+            },
+        },
+    };
+    number.parse::<i64>().ok().map(|n| n * unit_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+
+    #[test]
+    fn theme_is_active_before_and_inactive_after_expiry() {
+        let theme = ThemeSettings { word: "snow".to_string(), expires_at_unix: 1_000 };
+        assert!(theme.is_active(999));
+        assert!(!theme.is_active(1_000));
+        assert!(!theme.is_active(1_001));
+    }
+
+    #[test]
+    fn parse_duration_secs_handles_units() {
+        assert_eq!(parse_duration_secs("24h"), Some(24 * 3_600));
+        assert_eq!(parse_duration_secs("30m"), Some(30 * 60));
+        assert_eq!(parse_duration_secs("2d"), Some(2 * 86_400));
+        assert_eq!(parse_duration_secs("90s"), Some(90));
+        assert_eq!(parse_duration_secs("90"), Some(90));
+        assert_eq!(parse_duration_secs("nonsense"), None);
+    }
+
+    #[test]
+    fn generate_themed_prefers_the_theme_word_when_unseeded_and_active() {
+        let chain = ChainBuilder::new().msgs(&["snow is falling", "rain is falling"]).build();
+        let theme = ThemeSettings { word: "snow".to_string(), expires_at_unix: 100 };
+
+        let message = generate_themed(&chain, None, None, Some(&theme), 50).unwrap();
+        assert_eq!(message, "snow is falling");
+    }
+
+    #[test]
+    fn generate_themed_falls_back_when_the_chain_lacks_the_theme_word() {
+        let chain = ChainBuilder::new().msg("rain is falling").build();
+        let theme = ThemeSettings { word: "snow".to_string(), expires_at_unix: 100 };
+
+        let message = generate_themed(&chain, None, None, Some(&theme), 50).unwrap();
+        assert_eq!(message, "rain is falling");
+    }
+
+    #[test]
+    fn generate_themed_ignores_an_expired_theme() {
+        let chain = ChainBuilder::new().msgs(&["snow is falling", "rain is falling"]).build();
+        let theme = ThemeSettings { word: "snow".to_string(), expires_at_unix: 100 };
+
+        // now_unix (100) is not before expires_at_unix (100), so the theme
+        // has expired and either seed may be picked randomly; just check
+        // that a message still comes out.
+        let message = generate_themed(&chain, None, None, Some(&theme), 100).unwrap();
+        assert!(!message.is_empty());
+    }
+
+    #[test]
+    fn generate_themed_never_overrides_an_explicit_seed() {
+        let chain = ChainBuilder::new().msgs(&["snow is falling", "rain is falling"]).build();
+        let theme = ThemeSettings { word: "snow".to_string(), expires_at_unix: 100 };
+
+        let message = generate_themed(&chain, Some("rain"), None, Some(&theme), 50).unwrap();
+        assert_eq!(message, "rain is falling");
+    }
+}
@@ -0,0 +1,84 @@
+//! Shared word normalization for learning messages into a
+//! [`crate::markov_chain::TripletMarkovChain`] and for resolving `/msg`
+//! seeds, so the same visible word compares equal regardless of the
+//! invisible formatting characters (zero-width joiners, bidi marks) or
+//! Unicode normalization form it happened to arrive in - e.g. a seed copied
+//! out of a message Telegram rendered with RTL embedding, or typed with an
+//! accent as a combining mark instead of a precomposed character.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Whether `c` is an invisible formatting character that should be stripped
+/// before a word is learned or looked up: zero-width joiners/spaces and bidi
+/// control marks. Emoji variation selectors (`FE0E`/`FE0F` and the
+/// supplementary `E0100..=E01EF` block) are deliberately not included here,
+/// since stripping one would change which glyph a codepoint renders as
+/// rather than just how the word is spaced or directioned.
+fn is_invisible_formatting_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}' // zero width space
+        | '\u{200C}' // zero width non-joiner
+        | '\u{200D}' // zero width joiner
+        | '\u{200E}' // left-to-right mark
+        | '\u{200F}' // right-to-left mark
+        | '\u{061C}' // Arabic letter mark
+        | '\u{FEFF}' // zero width no-break space / byte order mark
+        | '\u{202A}'..='\u{202E}' // LRE, RLE, PDF, LRO, RLO
+        | '\u{2066}'..='\u{2069}' // LRI, RLI, FSI, PDI
+    )
+}
+
+/// Normalizes a single word for both tokenization and seed lookup: strips
+/// invisible zero-width and bidi control characters, then NFC-normalizes
+/// what's left, so e.g. an NFD-decomposed accented word (a base letter plus
+/// a combining mark) compares equal to its NFC-precomposed form. Emoji with
+/// variation selectors round-trip unchanged.
+pub fn normalize_word(word: &str) -> String {
+    word.chars().filter(|&c| !is_invisible_formatting_char(c)).nfc().collect()
+}
+
+/// Splits `text` into normalized words on whitespace, dropping any token
+/// that normalizes to nothing (e.g. one made up entirely of invisible
+/// characters). See [`normalize_word`].
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(normalize_word)
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_word_strips_zero_width_and_bidi_characters() {
+        assert_eq!(normalize_word("hello\u{200D}world"), "helloworld");
+        assert_eq!(normalize_word("\u{200E}hello\u{200F}"), "hello");
+        assert_eq!(normalize_word("\u{2066}hello\u{2069}"), "hello");
+    }
+
+    #[test]
+    fn normalize_word_preserves_emoji_variation_selectors() {
+        assert_eq!(normalize_word("\u{2764}\u{FE0F}"), "\u{2764}\u{FE0F}");
+    }
+
+    #[test]
+    fn normalize_word_nfc_normalizes_nfd_accented_words() {
+        let nfd = "cafe\u{0301}"; // "café" with a combining acute accent
+        let nfc = "café";
+        assert_eq!(normalize_word(nfd), nfc);
+        assert_eq!(normalize_word(nfc), nfc);
+    }
+
+    #[test]
+    fn tokenize_drops_words_that_normalize_to_nothing() {
+        assert_eq!(tokenize("hello \u{200B} world"), vec!["hello", "world"]);
+    }
+
+    #[test]
+    fn tokenize_normalizes_each_word() {
+        assert_eq!(tokenize("\u{200E}hello\u{200F} world"), vec!["hello", "world"]);
+    }
+}
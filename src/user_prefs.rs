@@ -0,0 +1,63 @@
+//! Per-user `/msg` generation defaults, set via `/mydefaults` and persisted
+//! globally (i.e. not per-chat), so they follow a user from one chat to
+//! another.
+//!
+//! The bot's generation model only has one real tunable per invocation: a
+//! [`LengthRequirement`] (the same trailing token `/msg` itself accepts, e.g.
+//! `>8`). There's no temperature-weighted sampling or multi-message "count"
+//! knob anywhere in [`crate::markov_chain`] to attach a default to, so this
+//! module only exposes a default length, rather than inventing knobs the
+//! generator doesn't have.
+
+use serde::{Deserialize, Serialize};
+
+use crate::markov_chain::LengthRequirement;
+
+/// A user's persisted `/msg` defaults, global across every chat they're in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserPrefs {
+    pub user_id: i64,
+    /// Applied when a `/msg` invocation doesn't specify its own trailing
+    /// length token; see [`resolve_msg_length_requirement`].
+    #[serde(default)]
+    pub default_length_requirement: Option<LengthRequirement>,
+}
+
+/// Merges a `/msg` invocation's explicit trailing length token (if any) with
+/// the user's persisted default, in priority order: an explicit token always
+/// wins, falling back to the user's default, falling back to no requirement
+/// at all. (This bot has no chat-level default-length setting to sit between
+/// those two tiers - [`crate::auto_prune::ChatSettings`] has nothing
+/// analogous today - so the merge is two tiers, not three.)
+pub fn resolve_msg_length_requirement(
+    explicit: Option<LengthRequirement>,
+    user_default: Option<LengthRequirement>,
+) -> Option<LengthRequirement> {
+    explicit.or(user_default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_length_always_wins_over_the_user_default() {
+        assert_eq!(
+            resolve_msg_length_requirement(Some(LengthRequirement::Exactly(3)), Some(LengthRequirement::AtLeast(8))),
+            Some(LengthRequirement::Exactly(3))
+        );
+    }
+
+    #[test]
+    fn user_default_is_used_when_no_explicit_length_is_given() {
+        assert_eq!(
+            resolve_msg_length_requirement(None, Some(LengthRequirement::AtLeast(8))),
+            Some(LengthRequirement::AtLeast(8))
+        );
+    }
+
+    #[test]
+    fn no_requirement_at_all_when_neither_tier_sets_one() {
+        assert_eq!(resolve_msg_length_requirement(None, None), None);
+    }
+}